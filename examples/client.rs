@@ -19,7 +19,7 @@ async fn handle_connection(addr: &str) {
                         match result {
                             Ok(message) => {
                                  info!("Received message: {}", message.as_text().unwrap());
-                                counter = counter + 1;
+                                counter += 1;
                                 // close the connection if 3 messages have already been sent and received
                                 if counter >= 3 {
                                     if ws_connection.close_connection().await.is_err() {
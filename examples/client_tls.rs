@@ -8,8 +8,10 @@ use tokio::select;
 use tokio::time::{interval, Duration};
 
 async fn handle_connection(addr: &str) {
-    let mut client_config = ClientConfig::default();
-    client_config.ca_file = Some(String::from("ca.crt"));
+    let client_config = ClientConfig {
+        ca_file: Some(String::from("ca.crt")),
+        ..Default::default()
+    };
 
     match connect_async_with_config(addr, Some(client_config)).await {
         Ok(mut ws_connection) => {
@@ -23,7 +25,7 @@ async fn handle_connection(addr: &str) {
                         match result {
                             Ok(message) => {
                                  info!("Received message: {}", message.as_text().unwrap());
-                                counter = counter + 1;
+                                counter += 1;
                                 // close the connection if 3 messages have already been sent and received
                                 if counter >= 3 {
                                     if ws_connection.close_connection().await.is_err() {
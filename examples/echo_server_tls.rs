@@ -1,14 +1,11 @@
 use futures::StreamExt;
 use log::{error, info};
-use pki_types::{CertificateDer, PrivateKeyDer};
-use rustls_pemfile::{certs, private_key};
+use socket_flow::error::Error;
 use socket_flow::handshake::accept_async;
 use socket_flow::stream::SocketFlowStream;
-use std::fs::File;
-use std::io::{self, BufReader, ErrorKind};
+use socket_flow::tls::server_config_from_pem_files;
+use std::io::{self, ErrorKind};
 use std::net::{SocketAddr, ToSocketAddrs};
-use std::path::Path;
-use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream};
 use tokio_rustls::{TlsAcceptor, TlsStream};
 
@@ -33,21 +30,8 @@ async fn handle_connection(_: SocketAddr, stream: TlsStream<TcpStream>) {
         Err(err) => error!("Error when performing handshake: {}", err),
     }
 }
-fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
-    certs(&mut BufReader::new(File::open(path)?)).collect()
-}
-
-fn load_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
-    Ok(private_key(&mut BufReader::new(File::open(path)?))
-        .unwrap()
-        .ok_or(io::Error::new(
-            ErrorKind::Other,
-            "no private key found".to_string(),
-        ))?)
-}
-
 #[tokio::main]
-async fn main() -> io::Result<()> {
+async fn main() -> Result<(), Error> {
     env_logger::init();
 
     let addr = String::from("127.0.0.1:9002")
@@ -55,15 +39,9 @@ async fn main() -> io::Result<()> {
         .next()
         .ok_or_else(|| io::Error::from(ErrorKind::AddrNotAvailable))?;
 
-    let certs = load_certs(Path::new("cert.pem"))?;
-    let key = load_key(Path::new("key.pem"))?;
-
-    let config = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, key)
-        .map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+    let config = server_config_from_pem_files("cert.pem", "key.pem")?;
 
-    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let acceptor = TlsAcceptor::from(config);
 
     let listener = TcpListener::bind(&addr).await?;
 
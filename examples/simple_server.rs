@@ -16,8 +16,13 @@ async fn main() {
             info!("Server started on address 127.0.0.1:{}", port);
             while let Some(event) = event_receiver.next().await {
                 match event {
-                    Event::NewClient(id, client_conn) => {
-                        info!("New client {} connected", id);
+                    Event::NewClient(id, client_conn, request) => {
+                        info!(
+                            "New client {} connected from {:?} on path {}",
+                            id,
+                            request.peer_addr(),
+                            request.path()
+                        );
                         clients.insert(id, client_conn);
                     }
                     Event::NewMessage(client_id, message) => {
@@ -32,6 +37,9 @@ async fn main() {
                     Event::Error(client_id, error) => {
                         error!("Error occurred for client {}: {:?}", client_id, error);
                     }
+                    Event::Rejected(client_id, addr, rejection) => {
+                        warn!("Rejected client {} from {}: {:?}", client_id, addr, rejection);
+                    }
                 }
             }
         }
@@ -1,35 +1,20 @@
 use futures::StreamExt;
-use log::{error, info};
-use pki_types::{CertificateDer, PrivateKeyDer};
+use log::{error, info, warn};
 use rustls::ServerConfig as RustlsConfig;
-use rustls_pemfile::{certs, private_key};
 use socket_flow::config::ServerConfig;
+use socket_flow::error::Error;
 use socket_flow::event::{Event, ID};
 use socket_flow::server::start_server_with_config;
 use socket_flow::split::WSWriter;
+use socket_flow::tls::server_config_from_pem_files;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io;
-use std::io::{BufReader, ErrorKind};
-use std::path::Path;
 use std::sync::Arc;
 
-fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
-    certs(&mut BufReader::new(File::open(path)?)).collect()
-}
-
-fn load_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
-    Ok(private_key(&mut BufReader::new(File::open(path)?))
-        .unwrap()
-        .ok_or(io::Error::new(
-            ErrorKind::Other,
-            "no private key found".to_string(),
-        ))?)
-}
-
 async fn run_server(port: u16, tls_config: Arc<RustlsConfig>) {
-    let mut server_config = ServerConfig::default();
-    server_config.tls_config = Option::from(tls_config);
+    let server_config = ServerConfig {
+        tls_config: Option::from(tls_config),
+        ..Default::default()
+    };
 
     match start_server_with_config(8080, Some(server_config)).await {
         Ok(mut event_receiver) => {
@@ -37,7 +22,7 @@ async fn run_server(port: u16, tls_config: Arc<RustlsConfig>) {
             info!("Server started on address 127.0.0.1:{}", port);
             while let Some(event) = event_receiver.next().await {
                 match event {
-                    Event::NewClient(id, client_conn) => {
+                    Event::NewClient(id, client_conn, _request) => {
                         info!("New client {} connected", id);
                         clients.insert(id, client_conn);
                     }
@@ -53,6 +38,9 @@ async fn run_server(port: u16, tls_config: Arc<RustlsConfig>) {
                     Event::Error(client_id, error) => {
                         error!("Error occurred for client {}: {:?}", client_id, error);
                     }
+                    Event::Rejected(client_id, addr, rejection) => {
+                        warn!("Rejected client {} from {}: {:?}", client_id, addr, rejection);
+                    }
                 }
             }
         }
@@ -63,20 +51,14 @@ async fn run_server(port: u16, tls_config: Arc<RustlsConfig>) {
 }
 
 #[tokio::main]
-async fn main() -> io::Result<()> {
+async fn main() -> Result<(), Error> {
     env_logger::init();
 
-    let certs = load_certs(Path::new("cert.pem"))?;
-    let key = load_key(Path::new("key.pem"))?;
-
-    let config = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, key)
-        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let config = server_config_from_pem_files("cert.pem", "key.pem")?;
 
     let port: u16 = 8080;
 
-    run_server(port, Arc::new(config)).await;
+    run_server(port, config).await;
 
     Ok(())
 }
@@ -1,7 +1,11 @@
 use crate::error::Error;
-use crate::message::Message;
+use crate::message::{Message, PreparedMessage};
+use crate::request::HandshakeRequest;
 use crate::split::{WSReader, WSWriter};
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+use crate::tls::TlsInfo;
 use futures::Stream;
+use std::net::SocketAddr;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
@@ -18,6 +22,8 @@ pub struct WSConnection {
     /// Implements futures::Stream,
     /// so the end-user can process all the incoming messages, using .next() method
     reader: WSReader,
+    /// The HTTP upgrade request that originated this connection, along with the socket addresses
+    request: HandshakeRequest,
 }
 
 // WSConnection has the reader attribute, which is already a ReceiverStream
@@ -38,8 +44,32 @@ impl Stream for WSConnection {
 }
 
 impl WSConnection {
-    pub fn new(writer: WSWriter, reader: WSReader) -> Self {
-        Self { writer, reader }
+    pub fn new(writer: WSWriter, reader: WSReader, request: HandshakeRequest) -> Self {
+        Self {
+            writer,
+            reader,
+            request,
+        }
+    }
+
+    /// Returns the HTTP upgrade request of this connection, which gives access to the path,
+    /// query parameters and headers.
+    /// For servers, this is the request sent by the client,
+    /// and for clients, the request sent to the server.
+    pub fn request(&self) -> &HandshakeRequest {
+        &self.request
+    }
+
+    /// Returns the address of the remote end of this connection
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.request.peer_addr()
+    }
+
+    /// Returns the TLS session details, like the peer certificate chain, subject and
+    /// negotiated ALPN protocol, or `None` if the connection isn't running over TLS
+    #[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+    pub fn tls_info(&self) -> Option<&TlsInfo> {
+        self.request.tls_info()
     }
 
     /// This function will split the connection into the `WSReader`, which is a stream of messages
@@ -63,6 +93,11 @@ impl WSConnection {
         self.writer.send_message(message).await
     }
 
+    /// Send a message that was encoded once, for many connections
+    pub async fn send_prepared(&mut self, prepared: &PreparedMessage) -> Result<(), Error> {
+        self.writer.send_prepared(prepared).await
+    }
+
     /// Send generic data, by default it considers OpCode Text
     pub async fn send(&mut self, data: Vec<u8>) -> Result<(), Error> {
         self.writer.send(data).await
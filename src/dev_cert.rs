@@ -0,0 +1,95 @@
+use crate::error::Error;
+use crate::tls::{client_config_from_ca_pem, server_config_from_pem};
+use pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rcgen::{CertificateParams, DnType, KeyPair, PKCS_ECDSA_P256_SHA256};
+use std::sync::Arc;
+use time::{Duration, OffsetDateTime};
+
+const VALIDITY_DAYS: i64 = 30;
+
+/// An ephemeral self-signed certificate, along with its private key, meant for local development
+/// and tests only.
+///
+/// The certificate uses a freshly generated ECDSA P-256 key, and is valid for 30 days, for all
+/// the hostnames and IP addresses it was generated for. Since it isn't issued by a trusted CA,
+/// clients need to trust it explicitly, like using the config returned by `client_config`.
+pub struct SelfSignedCertificate {
+    cert: CertificateDer<'static>,
+    cert_pem: String,
+    key: Vec<u8>,
+    key_pem: String,
+}
+
+impl SelfSignedCertificate {
+    /// Generates a new certificate, valid for the given hostnames and IP addresses,
+    /// like `["localhost", "127.0.0.1"]`. The first one is also used as the subject common name
+    pub fn generate(hostnames: &[&str]) -> Result<Self, Error> {
+        let common_name = hostnames.first().ok_or_else(|| {
+            Error::TlsConfigError(String::from("at least one hostname is required"))
+        })?;
+
+        // The key is generated by the same crypto provider selected for rustls
+        let key_pair = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).map_err(tls_config_error)?;
+
+        // IP literals are added as IP addresses to the Subject Alternative Names,
+        // and everything else as DNS names
+        let mut params = CertificateParams::new(
+            hostnames
+                .iter()
+                .map(|hostname| hostname.to_string())
+                .collect::<Vec<_>>(),
+        )
+        .map_err(tls_config_error)?;
+        params
+            .distinguished_name
+            .push(DnType::CommonName, *common_name);
+        let now = OffsetDateTime::now_utc();
+        // The certificate is valid a bit before now, for tolerating small clock differences
+        params.not_before = now - Duration::hours(1);
+        params.not_after = now + Duration::days(VALIDITY_DAYS);
+
+        let cert = params.self_signed(&key_pair).map_err(tls_config_error)?;
+
+        Ok(Self {
+            cert: cert.der().clone(),
+            cert_pem: cert.pem(),
+            key: key_pair.serialize_der(),
+            key_pem: key_pair.serialize_pem(),
+        })
+    }
+
+    /// The DER encoded certificate
+    pub fn cert_der(&self) -> &CertificateDer<'static> {
+        &self.cert
+    }
+
+    /// The DER encoded private key, in PKCS#8 format
+    pub fn key_der(&self) -> PrivateKeyDer<'static> {
+        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(self.key.clone()))
+    }
+
+    /// The PEM encoded certificate
+    pub fn cert_pem(&self) -> String {
+        self.cert_pem.clone()
+    }
+
+    /// The PEM encoded private key, in PKCS#8 format
+    pub fn key_pem(&self) -> String {
+        self.key_pem.clone()
+    }
+
+    /// Builds a rustls server config using this certificate, to be set on `ServerConfig`
+    pub fn server_config(&self) -> Result<Arc<rustls::ServerConfig>, Error> {
+        server_config_from_pem(self.cert_pem.as_bytes(), self.key_pem.as_bytes())
+    }
+
+    /// Builds a rustls client config that trusts this certificate,
+    /// to be set as the `tls_config` of `ClientConfig`
+    pub fn client_config(&self) -> Result<Arc<rustls::ClientConfig>, Error> {
+        client_config_from_ca_pem(self.cert_pem.as_bytes())
+    }
+}
+
+fn tls_config_error(err: rcgen::Error) -> Error {
+    Error::TlsConfigError(err.to_string())
+}
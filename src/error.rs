@@ -1,9 +1,13 @@
+use crate::event::ID;
 use crate::frame::Frame;
+use crate::request::HttpResponse;
+#[cfg(feature = "rustls-tls")]
 use pki_types::InvalidDnsNameError;
 use std::io;
 use std::string::FromUtf8Error;
 use thiserror::Error;
 use tokio::sync::mpsc::error::SendError;
+use tokio::task::JoinError;
 use tokio::time::error::Elapsed;
 use url::ParseError;
 
@@ -26,6 +30,12 @@ pub enum Error {
         source: Elapsed,
     },
 
+    #[error("{source}")]
+    JoinError {
+        #[from]
+        source: JoinError,
+    },
+
     #[error("IO Error happened: {source}")]
     IOError {
         #[from]
@@ -42,10 +52,10 @@ pub enum Error {
     #[error("Invalid handshake request method and version")]
     InvalidHTTPHandshake,
 
-    #[error("Connection: Upgrade header missing in the request")]
+    #[error("Connection: Upgrade header missing in the handshake")]
     NoConnectionHeaderPresent,
 
-    #[error("Upgrade: websocket header missing in the request")]
+    #[error("Upgrade: websocket header missing in the handshake")]
     NoUpgradeHeaderPresent,
 
     #[error("Host header missing in the request")]
@@ -60,6 +70,38 @@ pub enum Error {
     #[error("Sever didn't send a valid Sec-WebSocket-Accept key")]
     InvalidAcceptKey,
 
+    #[error("Server rejected the handshake with status {} {}", .0.status(), .0.reason())]
+    HandshakeRejected(Box<HttpResponse>),
+
+    #[error("No route matches the request path `{0}`")]
+    NoRouteMatched(String),
+
+    #[error("Server agreed on an extension the client didn't offer: `{0}`")]
+    UnexpectedExtension(String),
+
+    #[error("Server selected a subprotocol the client didn't offer: `{0}`")]
+    UnexpectedProtocol(String),
+
+    // Redirect Errors
+    #[error("Exceeded the maximum of `{0}` redirects")]
+    TooManyRedirects(usize),
+
+    #[error("Redirect response has no Location header")]
+    RedirectWithoutLocation,
+
+    #[error("Refusing to follow a redirect from wss to an insecure URL: `{0}`")]
+    InsecureRedirect(String),
+
+    // Proxy Errors
+    #[error("Unsupported proxy scheme: `{0}`")]
+    UnsupportedProxyScheme(String),
+
+    #[error("Proxy refused to open a tunnel with status {} {}", .0.status(), .0.reason())]
+    ProxyRejected(Box<HttpResponse>),
+
+    #[error("SOCKS5 proxy error: {0}")]
+    Socks5Error(String),
+
     // Framing Errors
     #[error("RSV not zero")]
     RSVNotZero,
@@ -79,6 +121,12 @@ pub enum Error {
     #[error("Max message size reached")]
     MaxMessageSize,
 
+    #[error("Peer exceeded the inbound rate limit")]
+    RateLimitExceeded,
+
+    #[error("The closing handshake was already started")]
+    CloseSent,
+
     // Fragmentation Errors
     #[error("Invalid frame while there is a fragmented message in progress")]
     InvalidFrameFragmentation,
@@ -99,6 +147,9 @@ pub enum Error {
     #[error("Invalid HTTP request line")]
     InvalidHTTPRequestLine,
 
+    #[error("Invalid HTTP status line")]
+    InvalidHTTPStatusLine,
+
     #[error("Missing HTTP method")]
     MissingHTTPMethod,
 
@@ -130,6 +181,7 @@ pub enum Error {
     IncompleteHTTPRequest,
 
     // Domain addr parsing error
+    #[cfg(feature = "rustls-tls")]
     #[error("{source}")]
     DomainError {
         #[from]
@@ -139,6 +191,49 @@ pub enum Error {
     #[error("use_tls = `{0}` argument does not match the passed URL scheme: `{1}`")]
     SchemeAgainstTlsConfig(bool, String),
 
+    // TLS Errors
+    #[cfg(feature = "rustls-tls")]
+    #[error("{source}")]
+    RustlsError {
+        #[from]
+        source: rustls::Error,
+    },
+
+    #[cfg(feature = "native-tls")]
+    #[error("{source}")]
+    NativeTlsError {
+        #[from]
+        source: native_tls::Error,
+    },
+
+    #[error("Invalid TLS configuration: {0}")]
+    TlsConfigError(String),
+
+    #[error("wss URLs require the `aws-lc-rs`, `ring` or `native-tls` feature")]
+    TlsNotEnabled,
+
+    #[error("No PKCS#8, PKCS#1 or SEC1 private key found in `{0}`")]
+    NoPrivateKey(String),
+
+    #[error("No certificates found in `{0}`")]
+    NoCertificates(String),
+
+    #[error("Invalid PEM content in `{0}`: {1}")]
+    InvalidPem(String, String),
+
+    #[error("Couldn't find the system trusted CA certificates")]
+    NoNativeRoots,
+
+    // Server Errors
+    #[error("Server has no listeners, add one with `bind` or `listener`")]
+    NoListeners,
+
+    #[error("No connected client with ID `{0}`")]
+    UnknownClient(ID),
+
+    #[error("Status code `{0}` can't be sent in a Close frame")]
+    InvalidCloseCode(u16),
+
     // Compression / Decompression Errors
     #[error("max_window_bits should be a value between 8 and 15")]
     InvalidMaxWindowBits,
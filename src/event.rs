@@ -1,9 +1,12 @@
 use crate::error::Error;
+use crate::event_queue::EventReceiver;
 use crate::message::Message;
+use crate::request::HandshakeRequest;
 use crate::split::WSWriter;
 use futures::Stream;
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
+use std::net::{IpAddr, SocketAddr};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::sync::mpsc::Receiver;
@@ -21,23 +24,57 @@ pub fn generate_new_uuid() -> Uuid {
 
 // Base enum, used as the structure to represent every single event within
 // the websockets server, offering the end-user a practical way of spawning a server
-// and handling connections
+// and handling connections.
+// NewClient also carries the handshake request of the client, for accessing its path,
+// headers and remote address.
+// Rejected is sent for connections refused by the admission control of the server,
+// along with the client address
 pub enum Event {
-    NewClient(ID, WSWriter),
+    NewClient(ID, WSWriter, Box<HandshakeRequest>),
     NewMessage(ID, Message),
     Disconnect(ID),
     Error(ID, Error),
+    Rejected(ID, SocketAddr, Rejection),
+}
+
+/// Why the server refused a connection, answering it with 503 Service Unavailable.
+/// Rejections are reported only while the event channel has room, so they are dropped
+/// when it's full
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    /// The server reached `max_connections`
+    MaxConnections,
+    /// The client IP address reached `max_connections_per_ip`
+    MaxConnectionsPerIp(IpAddr),
+    /// The event channel is full, meaning the consumer of the events is falling behind
+    Overloaded,
 }
 
 // This struct will be used for implementing Stream trait. Thus, the end-user
-// doesn't need to interact with the mpsc tokio channel directly
+// doesn't need to interact with the mpsc tokio channel directly.
+// The server delivers its events through its own queue, which applies the
+// event channel policy of the ServerConfig.
+// Dropping it shuts down the server that feeds it.
 pub struct EventStream {
-    receiver: Receiver<Event>,
+    receiver: EventSource,
+}
+
+enum EventSource {
+    Channel(Receiver<Event>),
+    Queue(EventReceiver),
 }
 
 impl EventStream {
     pub fn new(receiver: Receiver<Event>) -> Self {
-        Self { receiver }
+        Self {
+            receiver: EventSource::Channel(receiver),
+        }
+    }
+
+    pub(crate) fn from_queue(receiver: EventReceiver) -> Self {
+        Self {
+            receiver: EventSource::Queue(receiver),
+        }
     }
 }
 
@@ -46,6 +83,9 @@ impl Stream for EventStream {
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.get_mut();
-        Pin::new(&mut this.receiver).poll_recv(cx)
+        match &mut this.receiver {
+            EventSource::Channel(receiver) => receiver.poll_recv(cx),
+            EventSource::Queue(receiver) => receiver.poll_recv(cx),
+        }
     }
 }
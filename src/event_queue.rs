@@ -0,0 +1,213 @@
+use crate::config::EventChannelPolicy;
+use crate::event::Event;
+use futures::task::AtomicWaker;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::sync::Notify;
+
+// A bounded queue, delivering the server events to the EventStream.
+// Unlike a tokio channel, it can make room by dropping its oldest messages, and lets the
+// server tasks wait for the EventStream to be dropped.
+// Only NewMessage events are ever dropped or refused, since losing a NewClient or a Disconnect
+// would leave the consumer with a wrong view of the connections.
+pub(crate) fn event_queue(
+    capacity: usize,
+    policy: EventChannelPolicy,
+) -> (EventSender, EventReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(QueueState {
+            events: VecDeque::new(),
+            senders: 1,
+            receiver_closed: false,
+        }),
+        capacity: capacity.max(1),
+        policy,
+        receiver_waker: AtomicWaker::new(),
+        changed: Notify::new(),
+    });
+
+    (
+        EventSender {
+            shared: shared.clone(),
+        },
+        EventReceiver { shared },
+    )
+}
+
+// Why an event couldn't be queued
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum SendError {
+    // The EventStream was dropped
+    Closed,
+    // The queue is full, and the DisconnectClient policy refuses new messages
+    Full,
+}
+
+struct Shared {
+    state: Mutex<QueueState>,
+    capacity: usize,
+    policy: EventChannelPolicy,
+    receiver_waker: AtomicWaker,
+    // Notified when events are taken from the queue, or when the receiver is dropped,
+    // waking the senders waiting on them
+    changed: Notify,
+}
+
+struct QueueState {
+    events: VecDeque<Event>,
+    senders: usize,
+    receiver_closed: bool,
+}
+
+pub(crate) struct EventSender {
+    shared: Arc<Shared>,
+}
+
+impl EventSender {
+    // Queues an event, waiting for room when the queue is full, unless the policy
+    // makes room by dropping the oldest message, or refuses new messages
+    pub(crate) async fn send(&self, event: Event) -> Result<(), SendError> {
+        let is_message = matches!(event, Event::NewMessage(..));
+        loop {
+            let changed = self.shared.changed.notified();
+            tokio::pin!(changed);
+            // Registering for notifications before checking, for not missing a change
+            // that happens in between
+            changed.as_mut().enable();
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if state.receiver_closed {
+                    return Err(SendError::Closed);
+                }
+
+                let has_room = state.events.len() < self.shared.capacity
+                    || match self.shared.policy {
+                        EventChannelPolicy::Block => false,
+                        EventChannelPolicy::DropOldest => state.drop_oldest_message(),
+                        EventChannelPolicy::DisconnectClient if is_message => {
+                            return Err(SendError::Full)
+                        }
+                        EventChannelPolicy::DisconnectClient => false,
+                    };
+                if has_room {
+                    state.events.push_back(event);
+                    drop(state);
+                    self.shared.receiver_waker.wake();
+                    return Ok(());
+                }
+            }
+            changed.await;
+        }
+    }
+
+    // Queues an event only if there is room right away, without waiting for it,
+    // or dropping other events
+    pub(crate) fn try_send(&self, event: Event) -> Result<(), SendError> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.receiver_closed {
+            return Err(SendError::Closed);
+        }
+        if state.events.len() >= self.shared.capacity {
+            return Err(SendError::Full);
+        }
+
+        state.events.push_back(event);
+        drop(state);
+        self.shared.receiver_waker.wake();
+        Ok(())
+    }
+
+    // Whether the queue is full, meaning the consumer of the events is falling behind
+    pub(crate) fn is_full(&self) -> bool {
+        self.shared.state.lock().unwrap().events.len() >= self.shared.capacity
+    }
+
+    // Resolves once the EventStream is dropped
+    pub(crate) async fn closed(&self) {
+        loop {
+            let changed = self.shared.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            if self.shared.state.lock().unwrap().receiver_closed {
+                return;
+            }
+            changed.await;
+        }
+    }
+}
+
+impl Clone for EventSender {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for EventSender {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            // Waking the receiver, so the stream ends
+            self.shared.receiver_waker.wake();
+        }
+    }
+}
+
+impl QueueState {
+    // Drops the oldest queued message, returning false if there is none
+    fn drop_oldest_message(&mut self) -> bool {
+        match self
+            .events
+            .iter()
+            .position(|event| matches!(event, Event::NewMessage(..)))
+        {
+            Some(position) => {
+                self.events.remove(position);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+pub(crate) struct EventReceiver {
+    shared: Arc<Shared>,
+}
+
+impl EventReceiver {
+    // Takes the next event, ending once all senders are dropped and the queue is empty
+    pub(crate) fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        // Registering before checking, for not missing an event queued in between
+        self.shared.receiver_waker.register(cx.waker());
+
+        let mut state = self.shared.state.lock().unwrap();
+        match state.events.pop_front() {
+            Some(event) => {
+                drop(state);
+                self.shared.changed.notify_waiters();
+                Poll::Ready(Some(event))
+            }
+            None if state.senders == 0 => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for EventReceiver {
+    fn drop(&mut self) {
+        let events = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.receiver_closed = true;
+            std::mem::take(&mut state.events)
+        };
+        // The queued events hold the writers of new clients, which are dropped
+        // outside the lock
+        drop(events);
+        self.shared.changed.notify_waiters();
+    }
+}
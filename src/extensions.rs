@@ -1,3 +1,5 @@
+use crate::error::Error;
+
 const PERMESSAGE_DEFLATE: &str = "permessage-deflate";
 const CLIENT_NO_CONTEXT_TAKEOVER: &str = "client_no_context_takeover";
 const SERVER_NO_CONTEXT_TAKEOVER: &str = "server_no_context_takeover";
@@ -60,14 +62,14 @@ pub fn parse_extensions(extensions_header_value: String) -> Option<Extensions> {
                 extensions.client_max_window_bits = Some(15);
             } else {
                 extensions.client_max_window_bits =
-                    extension_str.trim().split('=').last()?.parse::<u8>().ok();
+                    extension_str.trim().split('=').next_back()?.parse::<u8>().ok();
             }
         } else if extension_str.trim().starts_with(SERVER_MAX_WINDOW_BITS) {
             if !extension_str.contains('=') {
                 extensions.server_max_window_bits = Some(15);
             } else {
                 extensions.server_max_window_bits =
-                    extension_str.trim().split('=').last()?.parse::<u8>().ok();
+                    extension_str.trim().split('=').next_back()?.parse::<u8>().ok();
             }
         }
     }
@@ -96,14 +98,15 @@ pub fn merge_extensions(
         server_no_context_takeover: server_ext
             .server_no_context_takeover
             .and(client_ext.server_no_context_takeover),
+        // Per RFC 7692, client_max_window_bits can only be sent back if the client offered it,
+        // otherwise the client compresses with the default window
         client_max_window_bits: match (
             server_ext.client_max_window_bits,
             client_ext.client_max_window_bits,
         ) {
             (Some(server_bits), Some(client_bits)) => Some(std::cmp::min(server_bits, client_bits)),
-            (Some(server_bits), None) => Some(server_bits),
             (None, Some(client_bits)) => Some(client_bits),
-            (None, None) => None,
+            (_, None) => None,
         },
         server_max_window_bits: match (
             server_ext.server_max_window_bits,
@@ -118,6 +121,50 @@ pub fn merge_extensions(
     Some(merged_extensions)
 }
 
+// Client will use this function, to validate the extensions agreed by the server,
+// since the server can't enable an extension, or a parameter, that the client hasn't offered.
+// Per RFC 7692, the server may only send client_max_window_bits if the client has offered it,
+// while the remaining permessage-deflate parameters can be freely included by the server
+pub(crate) fn validate_server_extensions(
+    extensions_header_value: &str,
+    offered_extensions: Option<&Extensions>,
+) -> Result<(), Error> {
+    for extension in extensions_header_value
+        .split(',')
+        .filter(|extension| !extension.trim().is_empty())
+    {
+        let mut params = extension.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let offered = match offered_extensions {
+            Some(offered) if offered.permessage_deflate && name == PERMESSAGE_DEFLATE => offered,
+            _ => return Err(Error::UnexpectedExtension(name.to_string())),
+        };
+
+        for param in params {
+            let (key, value) = match param.split_once('=') {
+                Some((key, value)) => (key.trim(), Some(value.trim().trim_matches('"'))),
+                None => (param, None),
+            };
+
+            match key {
+                CLIENT_NO_CONTEXT_TAKEOVER | SERVER_NO_CONTEXT_TAKEOVER => {}
+                CLIENT_MAX_WINDOW_BITS if offered.client_max_window_bits.is_none() => {
+                    return Err(Error::UnexpectedExtension(param.to_string()));
+                }
+                CLIENT_MAX_WINDOW_BITS | SERVER_MAX_WINDOW_BITS => {
+                    match value.and_then(|bits| bits.parse::<u8>().ok()) {
+                        Some(8..=15) => {}
+                        _ => return Err(Error::InvalidMaxWindowBits),
+                    }
+                }
+                _ => return Err(Error::UnexpectedExtension(param.to_string())),
+            }
+        }
+    }
+
+    Ok(())
+}
+
 // Function used for constructing the HTTP request headers for extensions
 pub fn add_extension_headers(request: &mut String, extensions: Option<Extensions>) {
     match extensions {
@@ -57,4 +57,23 @@ impl Frame {
             compressed,
         }
     }
+
+    // A Close frame, carrying a status code and a reason.
+    // Control frames are limited to 125 bytes, so the reason is truncated to fit
+    pub fn close(code: u16, reason: &str) -> Self {
+        let mut end = reason.len().min(123);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        Self::new(true, OpCode::Close, payload, false)
+    }
+}
+
+// Whether a status code may be sent in a Close frame, per section 7.4 of the WebSockets RFC,
+// along with the codes registered by IANA, and the ones reserved for libraries and applications.
+// 1005, 1006 and 1015 are only meant for reporting, and must never be sent
+pub(crate) fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
 }
@@ -0,0 +1,156 @@
+use crate::error::Error;
+use crate::message::Message;
+use crate::request::HandshakeRequest;
+use crate::server::{ClientConnection, ClientInfo};
+use crate::split::{WSReader, WSSender};
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use log::error;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+
+// Status codes sent when a handler refuses or fails a connection, per the WebSockets RFC
+const CLOSE_POLICY_VIOLATION: u16 = 1008;
+const CLOSE_INTERNAL_ERROR: u16 = 1011;
+const INTERNAL_ERROR_REASON: &str = "internal error";
+// How long a closed client has for completing the closing handshake
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Errors returned by a `Handler`
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Handles the connections of a server, one at a time per connection, through
+/// `ClientStream::serve`.
+///
+/// Each connection is run in its own task, with its own `State`, created by `on_connect`,
+/// so a failing, or even panicking, connection doesn't affect the other ones.
+/// The handler itself is shared between all connections, so application state, like a
+/// database pool, can be kept in its fields.
+pub trait Handler: Send + Sync + 'static {
+    /// The state of a single connection
+    type State: Send + 'static;
+
+    /// Called once a client is upgraded, creating the state of its connection.
+    /// Returning an error closes the connection with status 1008 (policy violation),
+    /// and the error as the reason, without calling `on_close`.
+    fn on_connect<'a>(
+        &'a self,
+        client: &'a ClientInfo,
+        request: &'a HandshakeRequest,
+    ) -> BoxFuture<'a, Result<Self::State, BoxError>>;
+
+    /// Called for every message of the client, returning an optional reply, which is
+    /// sent back to it.
+    /// Returning an error closes the connection with status 1011 (internal error).
+    fn on_message<'a>(
+        &'a self,
+        state: &'a mut Self::State,
+        message: Message,
+    ) -> BoxFuture<'a, Result<Option<Message>, BoxError>>;
+
+    /// Called once the connection is over, with its state, and the reason it was closed
+    fn on_close(&self, state: Self::State, reason: CloseReason) -> BoxFuture<'_, ()>;
+}
+
+/// Why a connection run by a `Handler` was closed
+#[derive(Debug)]
+pub enum CloseReason {
+    /// The connection was closed, by the client or the server
+    Closed,
+    /// Reading from, or writing into, the connection failed
+    Error(Error),
+    /// `on_message` returned an error, or panicked
+    HandlerError(BoxError),
+}
+
+// Runs the handler over a single connection, from on_connect to on_close
+pub(crate) async fn handle_client<H: Handler>(handler: Arc<H>, client: ClientConnection) {
+    let (info, connection) = client.into_parts();
+    let connect = AssertUnwindSafe(handler.on_connect(&info, connection.request()))
+        .catch_unwind()
+        .await;
+    let (mut reader, mut writer) = connection.split();
+    let sender = writer.sender();
+
+    let mut state = match connect {
+        Ok(Ok(state)) => state,
+        Ok(Err(err)) => {
+            close(
+                &mut reader,
+                &sender,
+                CLOSE_POLICY_VIOLATION,
+                &err.to_string(),
+            )
+            .await;
+            return;
+        }
+        Err(_) => {
+            error!("Handler panicked accepting client {}", info.id());
+            close(
+                &mut reader,
+                &sender,
+                CLOSE_INTERNAL_ERROR,
+                INTERNAL_ERROR_REASON,
+            )
+            .await;
+            return;
+        }
+    };
+
+    let reason = loop {
+        let message = match reader.next().await {
+            Some(Ok(message)) => message,
+            Some(Err(err)) => break CloseReason::Error(err),
+            None => break CloseReason::Closed,
+        };
+
+        let handled = AssertUnwindSafe(handler.on_message(&mut state, message))
+            .catch_unwind()
+            .await;
+        let reply = match handled {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(err)) => {
+                close(
+                    &mut reader,
+                    &sender,
+                    CLOSE_INTERNAL_ERROR,
+                    INTERNAL_ERROR_REASON,
+                )
+                .await;
+                break CloseReason::HandlerError(err);
+            }
+            Err(_) => {
+                error!(
+                    "Handler panicked handling a message of client {}",
+                    info.id()
+                );
+                close(
+                    &mut reader,
+                    &sender,
+                    CLOSE_INTERNAL_ERROR,
+                    INTERNAL_ERROR_REASON,
+                )
+                .await;
+                break CloseReason::HandlerError("handler panicked".into());
+            }
+        };
+        if let Some(reply) = reply {
+            if let Err(err) = writer.send_message(reply).await {
+                break CloseReason::Error(err);
+            }
+        }
+    };
+
+    handler.on_close(state, reason).await;
+}
+
+// Starts the closing handshake, and waits for the client to complete it
+async fn close(reader: &mut WSReader, sender: &WSSender, code: u16, reason: &str) {
+    let _ = sender.close(code, reason).await;
+    let _ = timeout(CLOSE_TIMEOUT, async {
+        while reader.next().await.is_some() {}
+    })
+    .await;
+}
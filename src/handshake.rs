@@ -3,24 +3,32 @@ use crate::connection::WSConnection;
 use crate::decoder::Decoder;
 use crate::encoder::Encoder;
 use crate::error::Error;
-use crate::extensions::{add_extension_headers, merge_extensions, parse_extensions, Extensions};
+use crate::extensions::{
+    add_extension_headers, merge_extensions, parse_extensions, validate_server_extensions,
+    Extensions,
+};
+use crate::http::HttpHandler;
 use crate::message::Message;
+use crate::proxy::{proxy_from_env, Proxy};
 use crate::read::ReadStream;
-use crate::request::{construct_http_request, HttpRequest};
+use crate::request::{
+    construct_http_request, is_redirect, resolve_redirect, HandshakeRequest, HttpRequest,
+    HttpResponse, HTTP_HEAD_TIMEOUT,
+};
+use crate::resolver::connect_tcp;
+use crate::router::Router;
 use crate::split::{WSReader, WSWriter};
 use crate::stream::SocketFlowStream;
-use crate::utils::{generate_websocket_accept_value, generate_websocket_key};
+use crate::tls::{connect_tls, TLS_ENABLED};
+use crate::utils::{generate_websocket_accept_value, generate_websocket_key, split_host_port};
 use crate::write::{Writer, WriterKind};
-use std::fs::File;
-use std::io::BufReader as SyncBufReader;
-use std::path::Path;
 use std::sync::Arc;
 use tokio::io::{split, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
-use tokio::net::TcpStream;
 use tokio::sync::mpsc::channel;
 use tokio::sync::Mutex;
-use tokio_rustls::{TlsConnector, TlsStream};
+use tokio::time::{timeout, Duration};
 use tokio_stream::wrappers::ReceiverStream;
+use url::Url;
 
 pub(crate) const HTTP_ACCEPT_RESPONSE: &str = "HTTP/1.1 101 Switching Protocols\r\n\
         Connection: Upgrade\r\n\
@@ -32,7 +40,12 @@ const HTTP_METHOD: &str = "GET";
 pub(crate) const SEC_WEBSOCKET_KEY: &str = "sec-websocket-key";
 pub(crate) const SEC_WEBSOCKET_EXTENSIONS: &str = "sec-websocket-extensions";
 pub(crate) const SEC_WEBSOCKET_ACCEPT: &str = "sec-websocket-accept";
+const SEC_WEBSOCKET_PROTOCOL: &str = "sec-websocket-protocol";
 const HOST: &str = "host";
+const UPGRADE: &str = "upgrade";
+const CONNECTION: &str = "connection";
+const WEBSOCKET: &str = "websocket";
+const LOCATION: &str = "location";
 
 pub type Result = std::result::Result<WSConnection, Error>;
 
@@ -50,13 +63,115 @@ pub async fn accept_async_with_config(
     stream: SocketFlowStream,
     config: Option<WebSocketConfig>,
 ) -> Result {
-    let (reader, mut write_half) = split(stream);
+    accept_upgrade(stream, config, None).await
+}
+
+/// Same as accept_async, where the configuration of the connection is picked by a `Router`,
+/// from the path and host of the request.
+///
+/// Routes without their own configuration use the given one.
+/// Requests matching no route are answered with 404 Not Found, instead of being upgraded,
+/// failing with `Error::NoRouteMatched`.
+pub async fn accept_async_with_router(
+    stream: SocketFlowStream,
+    router: &Router,
+    config: Option<WebSocketConfig>,
+) -> Result {
+    accept_upgrade(stream, config, Some(router)).await
+}
+
+// Performs the server side of the handshake, for connections accepted outside of the server,
+// where every request is either upgraded or fails
+async fn accept_upgrade(
+    stream: SocketFlowStream,
+    config: Option<WebSocketConfig>,
+    router: Option<&Router>,
+) -> Result {
+    let (buf_reader, write_half, request) =
+        read_handshake_request(stream, Some(HTTP_HEAD_TIMEOUT)).await?;
+    upgrade(buf_reader, write_half, request, config, router).await
+}
+
+// Performs the server side of the handshake, for connections accepted by the server.
+// Plain HTTP requests, without `Upgrade: websocket`, are answered by the HttpHandler, if any,
+// in which case there is no connection to return.
+// The request is read within the handshake deadline of the server, without a timeout of its own
+pub(crate) async fn accept_server(
+    stream: SocketFlowStream,
+    config: Option<WebSocketConfig>,
+    router: Option<&Router>,
+    http_handler: Option<&dyn HttpHandler>,
+) -> std::result::Result<Option<WSConnection>, Error> {
+    let (buf_reader, mut write_half, request) = read_handshake_request(stream, None).await?;
+
+    if let Some(http_handler) = http_handler {
+        if !is_upgrade_request(&request) {
+            let response = http_handler.respond(&request).await;
+            write_response(&mut write_half, &response).await?;
+            return Ok(None);
+        }
+    }
+
+    upgrade(buf_reader, write_half, request, config, router)
+        .await
+        .map(Some)
+}
+
+// Reads the handshake request within read_timeout, keeping the halves of the stream
+// for answering it
+async fn read_handshake_request(
+    stream: SocketFlowStream,
+    read_timeout: Option<Duration>,
+) -> std::result::Result<
+    (
+        BufReader<ReadHalf<SocketFlowStream>>,
+        WriteHalf<SocketFlowStream>,
+        HandshakeRequest,
+    ),
+    Error,
+> {
+    let peer_addr = stream.peer_addr().ok();
+    let local_addr = stream.local_addr().ok();
+    #[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+    let tls_info = stream.tls_info();
+    let (reader, write_half) = split(stream);
     let mut buf_reader = BufReader::new(reader);
 
+    let req = HttpRequest::parse_http_request_within(&mut buf_reader, read_timeout).await?;
+    let request = HandshakeRequest::new(req, peer_addr, local_addr);
+    #[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+    let request = request.with_tls_info(tls_info);
+    Ok((buf_reader, write_half, request))
+}
+
+// Upgrades the connection of a handshake request, where the configuration of the connection
+// may be picked by a router
+async fn upgrade(
+    buf_reader: BufReader<ReadHalf<SocketFlowStream>>,
+    mut write_half: WriteHalf<SocketFlowStream>,
+    mut request: HandshakeRequest,
+    config: Option<WebSocketConfig>,
+    router: Option<&Router>,
+) -> Result {
+    let (config, route) = match router.map(|router| router.find(&request)) {
+        None => (config, None),
+        Some(Some((route, route_match))) => (
+            route.web_socket_config().cloned().or(config),
+            Some(route_match),
+        ),
+        Some(None) => {
+            write_response(&mut write_half, &HttpResponse::new(404)).await?;
+            return Err(Error::NoRouteMatched(request.path().to_string()));
+        }
+    };
+
     let mut config = config.unwrap_or_default();
-    let parsed_extensions =
-        parse_handshake_server(&mut buf_reader, &mut write_half, config.extensions).await?;
-    config.extensions = parsed_extensions;
+    let protocol = route
+        .as_ref()
+        .and_then(|route| route.protocol().map(str::to_string));
+    config.extensions =
+        parse_handshake_server(&mut write_half, &request, config.extensions, protocol).await?;
+    request.set_route(route);
 
     let decoder_extensions = config.extensions.clone().unwrap_or_default();
     // The decoder will be reading and decompressing all client messages,
@@ -84,8 +199,40 @@ pub async fn accept_async_with_config(
         config,
         decoder,
         encoder,
+        request,
     )
-        .await
+    .await
+}
+
+// Whether the request asks for a WebSocket upgrade, as opposed to a plain HTTP request
+fn is_upgrade_request(request: &HandshakeRequest) -> bool {
+    request
+        .header(UPGRADE)
+        .is_some_and(|value| value.eq_ignore_ascii_case(WEBSOCKET))
+}
+
+// Answers a connection with a plain HTTP response, instead of upgrading it.
+// The request is read first, so the client gets the response instead of a connection reset
+pub(crate) async fn respond_without_upgrade(
+    stream: SocketFlowStream,
+    response: &HttpResponse,
+) -> std::result::Result<(), Error> {
+    let (reader, mut write_half) = split(stream);
+    let mut buf_reader = BufReader::new(reader);
+    HttpRequest::parse_http_request(&mut buf_reader).await?;
+
+    write_response(&mut write_half, response).await
+}
+
+// Writes a plain HTTP response, and shuts down the connection
+async fn write_response(
+    write_half: &mut WriteHalf<SocketFlowStream>,
+    response: &HttpResponse,
+) -> std::result::Result<(), Error> {
+    write_half.write_all(&response.to_bytes()).await?;
+    write_half.flush().await?;
+    write_half.shutdown().await?;
+    Ok(())
 }
 
 async fn second_stage_handshake(
@@ -95,6 +242,7 @@ async fn second_stage_handshake(
     config: WebSocketConfig,
     decoder: Decoder,
     encoder: Encoder,
+    request: HandshakeRequest,
 ) -> Result {
     // This writer instance would be used for writing frames into the socket.
     // Since it's going to be used by two different instances, we need to wrap it through an Arc
@@ -119,6 +267,7 @@ async fn second_stage_handshake(
     let ws_connection = WSConnection::new(
         WSWriter::new(connection_writer, config, encoder),
         WSReader::new(receiver_stream),
+        request,
     );
 
     // Spawning poll_messages which is the method for reading the frames from the socket concurrently,
@@ -127,11 +276,13 @@ async fn second_stage_handshake(
     // Since this is the only task that holds the ownership of BufReader, if some IO error happens,
     // poll_messages will return.
     // BufReader will be dropped, hence, the writeHalf and TCP connection
-    tokio::spawn(async move {
+    let read_task = tokio::spawn(async move {
         if let Err(err) = read_stream.poll_messages().await {
             let _ = read_stream.read_tx.send(Err(err)).await;
         }
     });
+    // Kept by the writer, so a connection can be force closed without reading from it anymore
+    writer.lock().await.set_read_task(read_task.abort_handle());
 
     Ok(ws_connection)
 }
@@ -146,60 +297,125 @@ pub async fn connect_async(addr: &str) -> Result {
 }
 
 /// Same as connect_async, with an additional argument for custom websocket connection configurations.
+///
+/// If `max_redirects` is set in the `ClientConfig`, redirect responses from the server will be
+/// followed, until the WebSocket endpoint is reached, or the maximum number of hops is exceeded.
+///
+/// Hostnames are resolved into multiple addresses, which are tried racing IPv6 and IPv4,
+/// and `connect_timeout` and `handshake_timeout` can be set in `ClientConfig`
+/// for limiting how long connecting may take.
 pub async fn connect_async_with_config(addr: &str, client_config: Option<ClientConfig>) -> Result {
+    match client_config
+        .as_ref()
+        .and_then(|config| config.handshake_timeout)
+    {
+        Some(deadline) => {
+            timeout(deadline, connect_async_with_redirects(addr, client_config)).await?
+        }
+        None => connect_async_with_redirects(addr, client_config).await,
+    }
+}
+
+async fn connect_async_with_redirects(addr: &str, client_config: Option<ClientConfig>) -> Result {
+    let config = client_config.clone().unwrap_or_default();
+    let mut url = Url::parse(addr)?;
+    let mut redirects = 0;
+
+    loop {
+        match connect_async_once(url.as_str(), client_config.clone()).await {
+            Err(Error::HandshakeRejected(response)) if is_redirect(response.status()) => {
+                let max_redirects = match config.max_redirects {
+                    Some(max_redirects) => max_redirects,
+                    None => return Err(Error::HandshakeRejected(response)),
+                };
+                if redirects >= max_redirects {
+                    return Err(Error::TooManyRedirects(max_redirects));
+                }
+
+                let location = response
+                    .header(LOCATION)
+                    .ok_or(Error::RedirectWithoutLocation)?;
+                url = resolve_redirect(&url, location, config.allow_insecure_redirects)?;
+                redirects += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn connect_async_once(addr: &str, client_config: Option<ClientConfig>) -> Result {
     let client_websocket_key = generate_websocket_key();
 
     let client_extensions = client_config.clone().unwrap_or_default().web_socket_config.extensions;
 
     let (request, hostname, host, use_tls) = construct_http_request(addr, &client_websocket_key, client_extensions)?;
+    // Failing before connecting, when this build can't open wss connections
+    if use_tls && !TLS_ENABLED {
+        return Err(Error::TlsNotEnabled);
+    }
 
-    let stream = TcpStream::connect(hostname).await?;
-
-    let maybe_ca_file = client_config.clone().unwrap_or_default().ca_file;
-    let maybe_tls = if use_tls {
-        // Creating a cert store, to inject the TLS certificates
-        let mut root_cert_store = rustls::RootCertStore::empty();
-
-        // In the case you are using self-signed certificates on the server
-        // you are trying to connect, you must indicate a CA certificate of this server
-        // when connecting to it.
-        // Since the server has a self-signed cert, the only way of this library validating
-        // the cert is adding as an argument of the connect_async function
-        if let Some(file) = maybe_ca_file {
-            let mut pem = SyncBufReader::new(File::open(Path::new(file.as_str()))?);
-            for cert in rustls_pemfile::certs(&mut pem) {
-                root_cert_store.add(cert?).unwrap();
+    let config = client_config.clone().unwrap_or_default();
+    let maybe_proxy = match config.proxy.clone() {
+        Some(proxy) => Some(proxy),
+        None if config.proxy_from_env => proxy_from_env(&host, use_tls),
+        None => None,
+    };
+    let connect = async {
+        match maybe_proxy {
+            Some(proxy) => {
+                let proxy = Proxy::parse(&proxy)?;
+                let (proxy_host, proxy_port) = proxy.address();
+                let mut stream =
+                    connect_tcp(proxy_host, proxy_port, config.resolver.as_ref()).await?;
+                proxy
+                    .tunnel(&mut stream, &hostname, config.resolver.as_ref())
+                    .await?;
+                Ok::<_, Error>(stream)
+            }
+            None => {
+                let (_, port) = split_host_port(&hostname)?;
+                Ok(connect_tcp(&host, port, config.resolver.as_ref()).await?)
             }
-        } else {
-            // Here we are adding TLS_SERVER_ROOTS to the certificate store,
-            // which is basically a reference to a list of trusted root certificates
-            // issue by a CA.
-            // In the case, you are establishing a connection with a server
-            // that has a valid trusted certificate.
-            // You won't need a CA file
-            root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
         }
+    };
+    let stream = match config.connect_timeout {
+        Some(connect_timeout) => timeout(connect_timeout, connect).await??,
+        None => connect.await?,
+    };
+    let peer_addr = stream.peer_addr().ok();
+    let local_addr = stream.local_addr().ok();
 
-        let config = rustls::ClientConfig::builder()
-            .with_root_certificates(root_cert_store)
-            .with_no_client_auth();
-        let connector = TlsConnector::from(Arc::new(config));
-
-        let domain = pki_types::ServerName::try_from(host)?;
-        let tls_stream = connector.connect(domain, stream).await?;
-        SocketFlowStream::Secure(TlsStream::from(tls_stream))
+    let maybe_tls = if use_tls {
+        connect_tls(stream, host, &config).await?
     } else {
         SocketFlowStream::Plain(stream)
     };
+    #[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+    let tls_info = maybe_tls.tls_info();
 
     let (reader, mut write_half) = split(maybe_tls);
     let mut buf_reader = BufReader::new(reader);
 
     write_half.write_all(request.as_bytes()).await?;
 
+    // The response is read within the handshake deadline when there is one,
+    // and otherwise within the same time given to servers for reading requests
+    let read_timeout = match config.handshake_timeout {
+        Some(_) => None,
+        None => Some(HTTP_HEAD_TIMEOUT),
+    };
     let mut config = client_config.unwrap_or_default().web_socket_config;
-    let extensions = parse_handshake_client(&mut buf_reader, client_websocket_key).await?;
+    let extensions = parse_handshake_client(
+        &mut buf_reader,
+        client_websocket_key,
+        config.extensions.as_ref(),
+        read_timeout,
+    )
+    .await?;
     config.extensions = extensions;
+    let request = HandshakeRequest::new(HttpRequest::from_head(&request)?, peer_addr, local_addr);
+    #[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+    let request = request.with_tls_info(tls_info);
 
     let decoder_extensions = config.extensions.clone().unwrap_or_default();
     // The decoder will be reading and decompressing all client messages,
@@ -226,40 +442,45 @@ pub async fn connect_async_with_config(addr: &str, client_config: Option<ClientC
         config,
         decoder,
         encoder,
+        request,
     )
         .await
 }
 
 async fn parse_handshake_server(
-    buf_reader: &mut BufReader<ReadHalf<SocketFlowStream>>,
     write_half: &mut WriteHalf<SocketFlowStream>,
+    req: &HandshakeRequest,
     server_extensions: Option<Extensions>,
+    protocol: Option<String>,
 ) -> std::result::Result<Option<Extensions>, Error> {
-    let mut req = HttpRequest::parse_http_request(buf_reader).await?;
-
     // Validate the WebSocket handshake
-    if !req.method.eq(HTTP_METHOD) {
+    if !req.method().eq(HTTP_METHOD) {
         return Err(Error::InvalidHTTPHandshake);
     }
 
-    if req.get_header_value(HOST).is_none() {
+    if req.header(HOST).is_none() {
         return Err(Error::NoHostHeaderPresent);
     }
 
-    let sec_websocket_key = match req.get_header_value(SEC_WEBSOCKET_KEY) {
+    let sec_websocket_key = match req.header(SEC_WEBSOCKET_KEY) {
         Some(key) => key.to_string(),
         None => Err(Error::NoSecWebsocketKey)?,
     };
 
     let client_extensions = parse_extensions(
-        req.get_header_value(SEC_WEBSOCKET_EXTENSIONS)
-            .unwrap_or_default(),
+        req.header(SEC_WEBSOCKET_EXTENSIONS)
+            .unwrap_or_default()
+            .to_string(),
     );
     let agreed_extensions = merge_extensions(server_extensions, client_extensions);
 
     let accept_key = generate_websocket_accept_value(sec_websocket_key);
 
     let mut response = HTTP_ACCEPT_RESPONSE.replace("{}", &accept_key);
+    // The subprotocol selected by the route of the connection, if any
+    if let Some(protocol) = protocol {
+        response.push_str(&format!("Sec-WebSocket-Protocol: {}\r\n", protocol));
+    }
     add_extension_headers(&mut response, agreed_extensions.clone());
 
     write_half
@@ -274,23 +495,48 @@ async fn parse_handshake_server(
 async fn parse_handshake_client(
     buf_reader: &mut BufReader<ReadHalf<SocketFlowStream>>,
     client_websocket_key: String,
+    offered_extensions: Option<&Extensions>,
+    read_timeout: Option<Duration>,
 ) -> std::result::Result<Option<Extensions>, Error> {
-    let mut req = HttpRequest::parse_http_request(buf_reader).await?;
+    let response = HttpResponse::parse_http_response(buf_reader, read_timeout).await?;
 
-    let expected_accept_value = generate_websocket_accept_value(client_websocket_key);
+    // Anything other than 101 means the server didn't upgrade the connection.
+    // The whole response is handed over to the caller,
+    // so it's possible to tell an authentication failure from an overloaded server
+    if response.status() != 101 {
+        return Err(Error::HandshakeRejected(Box::new(response)));
+    }
+
+    if !response
+        .header(UPGRADE)
+        .is_some_and(|value| value.eq_ignore_ascii_case(WEBSOCKET))
+    {
+        return Err(Error::NoUpgradeHeaderPresent);
+    }
+
+    // Connection header may contain multiple comma separated tokens, like "keep-alive, Upgrade"
+    if !response.header(CONNECTION).is_some_and(|value| {
+        value
+            .split(',')
+            .any(|token| token.trim().eq_ignore_ascii_case(UPGRADE))
+    }) {
+        return Err(Error::NoConnectionHeaderPresent);
+    }
 
-    // Some websockets server returns the SEC_WEBSOCKET_ACCEPT header, as lowercase.
-    // Therefore, we need to cover both cases, for the sake of having support, even though it's
-    // out of RFC standards
-    let sec_websocket_accept =  req.get_header_value(SEC_WEBSOCKET_ACCEPT).unwrap_or_default();
-    if !sec_websocket_accept.contains(&expected_accept_value) {
+    let expected_accept_value = generate_websocket_accept_value(client_websocket_key);
+    if response.header(SEC_WEBSOCKET_ACCEPT) != Some(expected_accept_value.as_str()) {
         return Err(Error::InvalidAcceptKey);
     }
 
-    let extensions = parse_extensions(
-        req.get_header_value(SEC_WEBSOCKET_EXTENSIONS)
-            .unwrap_or_default(),
-    );
+    // This library doesn't offer any subprotocol, so the server must not select one
+    if let Some(protocol) = response.header(SEC_WEBSOCKET_PROTOCOL) {
+        return Err(Error::UnexpectedProtocol(protocol.to_string()));
+    }
+
+    let extensions_header_value = response
+        .header(SEC_WEBSOCKET_EXTENSIONS)
+        .unwrap_or_default();
+    validate_server_extensions(extensions_header_value, offered_extensions)?;
 
-    Ok(extensions)
+    Ok(parse_extensions(extensions_header_value.to_string()))
 }
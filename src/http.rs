@@ -0,0 +1,90 @@
+use crate::request::{HandshakeRequest, HttpResponse};
+use futures::future::BoxFuture;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Answers plain HTTP requests arriving at the WebSocket port of a server.
+///
+/// By default, a request without `Upgrade: websocket` fails the handshake, and the connection
+/// is dropped. Implementing this trait, and setting it on `ServerConfig`, answers these requests
+/// with a regular HTTP response instead, like health checks sent by load balancers.
+/// The connection is closed after the response.
+pub trait HttpHandler: Debug + Send + Sync {
+    fn respond(&self, request: &HandshakeRequest) -> BoxFuture<'static, HttpResponse>;
+
+    /// Called once `ServerHandle::shutdown` starts, before the connections are closed.
+    /// Does nothing by default
+    fn on_shutdown(&self) {}
+}
+
+/// A built-in `HttpHandler`, with liveness and readiness endpoints for probes.
+///
+/// `/healthz` is answered with 200 OK while the server is running, and `/readyz` with 200 OK
+/// while it's ready, or 503 Service Unavailable otherwise, like right before shutting it down.
+/// The server marks it as not ready once its shutdown starts.
+/// Any other request is answered with 404 Not Found.
+/// Clones share the same readiness.
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    liveness_path: String,
+    readiness_path: String,
+    ready: Arc<AtomicBool>,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            liveness_path: String::from("/healthz"),
+            readiness_path: String::from("/readyz"),
+            ready: Arc::new(AtomicBool::new(true)),
+        }
+    }
+}
+
+impl HealthCheck {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the path of the liveness endpoint, `/healthz` by default
+    pub fn liveness_path(mut self, path: &str) -> Self {
+        self.liveness_path = path.to_string();
+        self
+    }
+
+    /// Replaces the path of the readiness endpoint, `/readyz` by default
+    pub fn readiness_path(mut self, path: &str) -> Self {
+        self.readiness_path = path.to_string();
+        self
+    }
+
+    /// Sets whether the server is ready to take connections, which is true by default
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Relaxed);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+}
+
+impl HttpHandler for HealthCheck {
+    fn respond(&self, request: &HandshakeRequest) -> BoxFuture<'static, HttpResponse> {
+        let response = if request.path() == self.liveness_path {
+            HttpResponse::new(200).with_body("ok")
+        } else if request.path() == self.readiness_path && self.is_ready() {
+            HttpResponse::new(200).with_body("ready")
+        } else if request.path() == self.readiness_path {
+            HttpResponse::new(503).with_body("not ready")
+        } else {
+            HttpResponse::new(404)
+        };
+
+        Box::pin(async move { response })
+    }
+
+    fn on_shutdown(&self) {
+        self.set_ready(false);
+    }
+}
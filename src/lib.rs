@@ -11,18 +11,29 @@
 pub mod config;
 pub mod connection;
 mod decoder;
+#[cfg(all(feature = "dev-certs", any(feature = "aws-lc-rs", feature = "ring")))]
+pub mod dev_cert;
 mod encoder;
 pub mod error;
 pub mod event;
+mod event_queue;
 pub mod extensions;
 mod frame;
+pub mod handler;
 pub mod handshake;
+pub mod http;
 pub mod message;
+mod proxy;
+mod rate_limit;
 mod read;
-mod request;
+pub mod request;
+pub mod resolver;
+pub mod rooms;
+pub mod router;
 pub mod server;
 pub mod split;
 pub mod stream;
 mod tests;
+pub mod tls;
 mod utils;
 mod write;
@@ -1,5 +1,10 @@
+use crate::encoder::Encoder;
 use crate::error::Error;
 use crate::frame::{Frame, OpCode};
+use crate::split::split_into_frames;
+use crate::write::server_frame_header;
+use bytes::{Bytes, BytesMut};
+use std::sync::{Arc, OnceLock};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Message {
@@ -33,3 +38,120 @@ impl Message {
         }
     }
 }
+
+/// A message encoded once, for sending the same message to many connections, like when
+/// broadcasting.
+///
+/// The message is framed as a single frame when it's created, and shared between all the
+/// server connections it's sent to, instead of being framed again, and copied, for each one of
+/// them.
+/// For connections that negotiated compression with `server_no_context_takeover`, and the default
+/// window, where every message is compressed on its own, it's also compressed only once, the first
+/// time it's sent to one of them.
+/// Messages larger than the `max_frame_size` of a connection are fragmented for it instead,
+/// like any other message.
+/// Client connections frame it again, since each one of their frames is masked differently.
+/// It's cheap to clone.
+#[derive(Clone)]
+pub struct PreparedMessage {
+    inner: Arc<PreparedInner>,
+}
+
+struct PreparedInner {
+    opcode: OpCode,
+    payload: Bytes,
+    encoded: Bytes,
+    // None when compressing the payload failed, so the uncompressed frame is sent instead
+    compressed: OnceLock<Option<Bytes>>,
+}
+
+impl PreparedMessage {
+    pub fn new(message: Message) -> Self {
+        let (opcode, payload) = match message {
+            Message::Text(text) => (OpCode::Text, Bytes::from(text.into_bytes())),
+            Message::Binary(data) => (OpCode::Binary, Bytes::from(data)),
+        };
+        let encoded = encode_frame(&opcode, &payload, false);
+
+        Self {
+            inner: Arc::new(PreparedInner {
+                opcode,
+                payload,
+                encoded,
+                compressed: OnceLock::new(),
+            }),
+        }
+    }
+
+    /// The size of the message payload, before compression
+    pub fn len(&self) -> usize {
+        self.inner.payload.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.payload.is_empty()
+    }
+
+    // The encoded frame, ready to be written into a server connection
+    pub(crate) fn encoded(&self, compressed: bool) -> &Bytes {
+        // Empty payloads aren't compressed
+        if !compressed || self.is_empty() {
+            return &self.inner.encoded;
+        }
+
+        self.inner
+            .compressed
+            .get_or_init(|| {
+                let mut encoder = Encoder::new(true, None);
+                let payload = encoder
+                    .compress(&mut BytesMut::from(&self.inner.payload[..]))
+                    .ok()?;
+                Some(encode_frame(&self.inner.opcode, &payload, true))
+            })
+            .as_ref()
+            .unwrap_or(&self.inner.encoded)
+    }
+
+    // Whether the encoded frame fits in max_frame_size, so it can be written as it is
+    pub(crate) fn fits(&self, compressed: bool, max_frame_size: usize) -> bool {
+        let (payload, _) = self.encoded_payload(compressed);
+        payload.len() <= max_frame_size
+    }
+
+    // The frames of the message, split in fragments of up to max_frame_size, for connections
+    // that need to encode it by themselves, or can't take it in a single frame.
+    // A compressed payload is split as it is, so only the first fragment has RSV1 set
+    pub(crate) fn frames(&self, compressed: bool, max_frame_size: usize) -> Vec<Frame> {
+        let (payload, compressed) = self.encoded_payload(compressed);
+        split_into_frames(
+            self.inner.opcode.clone(),
+            payload.to_vec(),
+            max_frame_size,
+            compressed,
+        )
+    }
+
+    // The payload of the encoded frame, along with whether it's compressed, which it may not be
+    // when compressing it failed
+    fn encoded_payload(&self, compressed: bool) -> (Bytes, bool) {
+        let encoded = self.encoded(compressed);
+        let header_length = match encoded[1] & 0x7F {
+            126 => 4,
+            127 => 10,
+            _ => 2,
+        };
+        (encoded.slice(header_length..), encoded[0] & 0x40 != 0)
+    }
+}
+
+// Encodes a final, unmasked frame, setting RSV1 for compressed payloads
+fn encode_frame(opcode: &OpCode, payload: &[u8], compressed: bool) -> Bytes {
+    let mut first_byte = 0x80 | opcode.as_u8();
+    if compressed {
+        first_byte |= 0x40;
+    }
+
+    let mut encoded = server_frame_header(first_byte, payload.len());
+    encoded.extend_from_slice(payload);
+    Bytes::from(encoded)
+}
@@ -0,0 +1,41 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+// A token bucket, refilled continuously at `rate` tokens per second, and holding up to one
+// second worth of tokens, which is the burst allowed after a quiet period.
+// Taking more tokens than available leaves the bucket in debt, so a single message larger
+// than the burst is still let through, and paid back by waiting.
+// A rate of zero lets no traffic through at all, waiting forever on any token taken
+pub(crate) struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(rate: usize) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    // Takes the given amount of tokens, returning how long the caller should wait
+    // for the bucket to pay back its debt, if it went negative
+    pub(crate) fn take(&mut self, amount: usize) -> Option<Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.tokens -= amount as f64;
+
+        if self.tokens < 0.0 && self.rate == 0.0 {
+            Some(Duration::MAX)
+        } else if self.tokens < 0.0 {
+            Some(Duration::from_secs_f64(-self.tokens / self.rate))
+        } else {
+            None
+        }
+    }
+}
@@ -1,8 +1,9 @@
-use crate::config::WebSocketConfig;
+use crate::config::{RateLimitPolicy, WebSocketConfig};
 use crate::decoder::Decoder;
 use crate::error::Error;
 use crate::frame::{Frame, OpCode};
 use crate::message::Message;
+use crate::rate_limit::TokenBucket;
 use crate::stream::SocketFlowStream;
 use crate::write::Writer;
 use bytes::BytesMut;
@@ -10,7 +11,10 @@ use std::sync::Arc;
 use tokio::io::{AsyncReadExt, BufReader, ReadHalf};
 use tokio::sync::mpsc::Sender;
 use tokio::sync::Mutex;
-use tokio::time::{timeout, Duration};
+use tokio::time::{sleep, timeout, Duration};
+
+// Status code for closing connections that violate a policy, like the rate limits
+const CLOSE_POLICY_VIOLATION: u16 = 1008;
 
 #[derive(Clone)]
 pub(crate) struct FragmentedMessage {
@@ -26,6 +30,8 @@ pub struct ReadStream {
     writer: Arc<Mutex<Writer>>,
     config: WebSocketConfig,
     decoder: Decoder,
+    message_limiter: Option<TokenBucket>,
+    byte_limiter: Option<TokenBucket>,
 }
 
 impl ReadStream {
@@ -37,6 +43,8 @@ impl ReadStream {
         decoder: Decoder,
     ) -> Self {
         let fragmented_message = None;
+        let message_limiter = config.max_messages_per_second.map(TokenBucket::new);
+        let byte_limiter = config.max_bytes_per_second.map(TokenBucket::new);
         Self {
             buf_reader: read,
             fragmented_message,
@@ -44,6 +52,8 @@ impl ReadStream {
             writer,
             config,
             decoder,
+            message_limiter,
+            byte_limiter,
         }
     }
 
@@ -58,6 +68,7 @@ impl ReadStream {
         loop {
             match self.read_frame().await {
                 Ok(frame) => {
+                    self.enforce_rate_limits(&frame).await?;
                     match frame.opcode {
                         // By default, in order to start a fragmented message, the first frame should have a Text or Binary opcode,
                         // with a FIN bit set to 0
@@ -156,6 +167,47 @@ impl ReadStream {
         Ok(())
     }
 
+    // Accounts a received frame against the inbound rate limits, and when the peer exceeds them,
+    // either waits until it's back within the limits, before reading the next frame,
+    // or closes the connection, depending on the rate limit policy
+    async fn enforce_rate_limits(&mut self, frame: &Frame) -> Result<(), Error> {
+        let byte_delay = self
+            .byte_limiter
+            .as_mut()
+            .and_then(|limiter| limiter.take(frame.payload.len()));
+        // Messages are counted when their final frame arrives
+        let message_delay = if frame.final_fragment && !frame.opcode.is_control() {
+            self.message_limiter
+                .as_mut()
+                .and_then(|limiter| limiter.take(1))
+        } else {
+            None
+        };
+
+        let delay = match byte_delay.into_iter().chain(message_delay).max() {
+            Some(delay) => delay,
+            None => return Ok(()),
+        };
+
+        match self.config.rate_limit_policy {
+            RateLimitPolicy::Delay => {
+                sleep(delay).await;
+                Ok(())
+            }
+            RateLimitPolicy::Close => {
+                self.writer
+                    .lock()
+                    .await
+                    .write_frame(
+                        Frame::close(CLOSE_POLICY_VIOLATION, "rate limit exceeded"),
+                        false,
+                    )
+                    .await?;
+                Err(Error::RateLimitExceeded)
+            }
+        }
+    }
+
     async fn send_pong_frame(&mut self, payload: Vec<u8>) -> Result<(), Error> {
         let pong_frame = Frame::new(true, OpCode::Pong, payload, false);
         self.writer
@@ -290,9 +342,13 @@ impl ReadStream {
     }
 
     pub async fn send_close_frame(&mut self) -> Result<(), Error> {
-        self.writer
-            .lock()
-            .await
+        let mut writer = self.writer.lock().await;
+        // When this side started the closing handshake, the received Close is the reply,
+        // and must not be answered again
+        if writer.close_sent() {
+            return Ok(());
+        }
+        writer
             .write_frame(Frame::new(true, OpCode::Close, Vec::new(), false), false)
             .await
     }
@@ -1,16 +1,25 @@
 use crate::error::Error;
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader, ReadHalf};
 use tokio::time::{timeout, Duration};
-use url::Url;
+use url::{Host, Url};
 use crate::extensions::{add_extension_headers, Extensions};
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+use crate::tls::TlsInfo;
+use crate::router::RouteMatch;
 
 const HTTP_REQUEST_DELIMITER: &str = "\r\n\r\n";
+const MAX_RESPONSE_BODY_SIZE: usize = 64 << 10;
+// Adding a timeout to reading the HTTP head, since some attackers may only connect to the TCP
+// endpoint, and froze without sending the HTTP handshake.
+// Therefore, we need to drop all these cases
+pub(crate) const HTTP_HEAD_TIMEOUT: Duration = Duration::from_secs(5);
 
 // Function used for client connection, parsing the ws/wss URL to http, for constructing the
 // handshake request, which includes the sec-websockets-key, the URL path, scheme and another relevant
 // info. This function also returns the hostname since this is necessary for establishing the TCP socket
-pub fn construct_http_request(
+pub(crate) fn construct_http_request(
     ws_url: &str,
     key: &str,
     extensions: Option<Extensions>
@@ -47,6 +56,14 @@ pub fn construct_http_request(
     // regardless ws_url is an IP or domain
     let host_with_port = format!("{}:{}", host, port);
 
+    // IPv6 literals must be enclosed in brackets in the Host header, and in the host:port string,
+    // which is how the URL crate returns them.
+    // Although, the bare address is needed for resolving it, and for the TLS server name
+    let bare_host = match parsed_url.host() {
+        Some(Host::Ipv6(address)) => address.to_string(),
+        _ => String::from(host),
+    };
+
     let request_path = match parsed_url.query() {
         Some(query) => format!("{}?{}", parsed_url.path(), query),
         None => parsed_url.path().to_string(),
@@ -64,12 +81,45 @@ pub fn construct_http_request(
 
     add_extension_headers(&mut request, extensions);
 
-    Ok((request, host_with_port, String::from(host), use_tls))
+    Ok((request, host_with_port, bare_host, use_tls))
+}
+
+// Only these redirect status codes are followed by the client,
+// since the handshake request can be safely repeated with all of them
+pub(crate) fn is_redirect(status: u16) -> bool {
+    matches!(status, 301 | 302 | 303 | 307 | 308)
+}
+
+// Resolves the Location header of a redirect response against the URL of the current request,
+// returning the next URL the client should connect to.
+// Location can be relative, and it may use http/https schemes, which are mapped to ws/wss.
+// Redirects from wss to ws are refused, unless explicitly allowed, since they would silently
+// drop TLS from the connection
+pub(crate) fn resolve_redirect(
+    current: &Url,
+    location: &str,
+    allow_insecure: bool,
+) -> Result<Url, Error> {
+    let mut next = current.join(location)?;
+
+    let scheme = match next.scheme() {
+        "ws" | "http" => "ws",
+        "wss" | "https" => "wss",
+        _ => return Err(Error::InvalidSchemeURL),
+    };
+    next.set_scheme(scheme)
+        .map_err(|_| Error::InvalidSchemeURL)?;
+
+    if current.scheme() == "wss" && scheme == "ws" && !allow_insecure {
+        return Err(Error::InsecureRedirect(next.to_string()));
+    }
+
+    Ok(next)
 }
 
 #[derive(Debug)]
 #[allow(dead_code)]
-pub struct HttpRequest {
+pub(crate) struct HttpRequest {
     pub method: String,
     pub uri: String,
     pub version: String,
@@ -81,20 +131,16 @@ impl HttpRequest {
     pub async fn parse_http_request<T: AsyncReadExt + Unpin>(
         reader: &mut BufReader<ReadHalf<T>>,
     ) -> Result<HttpRequest, Error> {
-        let mut buffer = String::new();
-
-        // Adding a timeout to the buffer read, since some attackers may only connect to the TCP
-        // endpoint, and froze without sending the HTTP handshake.
-        // Therefore, we need to drop all these cases
-        timeout(Duration::from_secs(5), async {
-            // Read headers until we find the blank line (\r\n\r\n)
-            while let Ok(bytes_read) = reader.read_line(&mut buffer).await {
-                if bytes_read == 0 || buffer.ends_with(HTTP_REQUEST_DELIMITER) {
-                    break;
-                }
-            }
-        })
-        .await?;
+        Self::parse_http_request_within(reader, Some(HTTP_HEAD_TIMEOUT)).await
+    }
+
+    // Same as parse_http_request, reading the head within read_timeout,
+    // or without a timeout, when the caller has its own deadline
+    pub(crate) async fn parse_http_request_within<T: AsyncReadExt + Unpin>(
+        reader: &mut BufReader<ReadHalf<T>>,
+        read_timeout: Option<Duration>,
+    ) -> Result<HttpRequest, Error> {
+        let buffer = read_http_head(reader, read_timeout).await?;
 
         // Split the headers from the body
         let (header_part, body_part) = match buffer.split_once("\r\n\r\n") {
@@ -102,21 +148,7 @@ impl HttpRequest {
             None => return Err(Error::HttpParseError),
         };
 
-        // Parse the request line (e.g., "GET /path HTTP/1.1")
-        let mut lines = header_part.lines();
-        let request_line = lines.next().ok_or(Error::InvalidHTTPRequestLine)?;
-        let mut parts = request_line.split_whitespace();
-        let method = parts.next().ok_or(Error::MissingHTTPMethod)?.to_string();
-        let uri = parts.next().ok_or(Error::MissingHTTPUri)?.to_string();
-        let version = parts.next().ok_or(Error::MissingHTTPVersion)?.to_string();
-
-        // Parse headers
-        let mut headers = HashMap::new();
-        for line in lines {
-            if let Some((key, value)) = line.split_once(": ") {
-                headers.insert(key.to_string().to_lowercase(), value.trim().to_string());
-            }
-        }
+        let (method, uri, version, headers) = parse_request_head(header_part)?;
 
         // Read the body based on Content-Length
         let body = if let Some(content_length) = headers.get("Content-Length") {
@@ -139,7 +171,406 @@ impl HttpRequest {
         })
     }
 
+    #[allow(dead_code)]
     pub fn get_header_value(&mut self, key: &str) -> Option<String> {
         self.headers.get(key).cloned()
     }
+
+    // Builds the HTTP request from an already serialized request head, which is what the client
+    // holds after constructing its own handshake request
+    pub fn from_head(head: &str) -> Result<HttpRequest, Error> {
+        let header_part = head
+            .split_once(HTTP_REQUEST_DELIMITER)
+            .map_or(head, |(h, _)| h);
+        let (method, uri, version, headers) = parse_request_head(header_part)?;
+
+        Ok(HttpRequest {
+            method,
+            uri,
+            version,
+            headers,
+            body: Vec::new(),
+        })
+    }
+}
+
+// Reads the HTTP head of a request or response, until the blank line (\r\n\r\n) is found,
+// within read_timeout, if any
+async fn read_http_head<T: AsyncReadExt + Unpin>(
+    reader: &mut BufReader<ReadHalf<T>>,
+    read_timeout: Option<Duration>,
+) -> Result<String, Error> {
+    let mut buffer = String::new();
+    let read = async {
+        while let Ok(bytes_read) = reader.read_line(&mut buffer).await {
+            if bytes_read == 0 || buffer.ends_with(HTTP_REQUEST_DELIMITER) {
+                break;
+            }
+        }
+    };
+    match read_timeout {
+        Some(read_timeout) => timeout(read_timeout, read).await?,
+        None => read.await,
+    }
+
+    Ok(buffer)
+}
+
+// Parses the header lines of an HTTP head, storing them with lowercase keys,
+// since HTTP header names are case-insensitive
+fn parse_headers<'a>(lines: impl Iterator<Item = &'a str>) -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    for line in lines {
+        if let Some((key, value)) = line.split_once(':') {
+            headers.insert(key.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+    headers
+}
+
+type RequestHead = (String, String, String, HashMap<String, String>);
+
+// Parses the request line (e.g., "GET /path HTTP/1.1") and the headers
+fn parse_request_head(header_part: &str) -> Result<RequestHead, Error> {
+    let mut lines = header_part.lines();
+    let request_line = lines.next().ok_or(Error::InvalidHTTPRequestLine)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().ok_or(Error::MissingHTTPMethod)?.to_string();
+    let uri = parts.next().ok_or(Error::MissingHTTPUri)?.to_string();
+    let version = parts.next().ok_or(Error::MissingHTTPVersion)?.to_string();
+
+    Ok((method, uri, version, parse_headers(lines)))
+}
+
+/// An HTTP response received by a client, while performing the WebSocket handshake.
+///
+/// When the server doesn't upgrade the connection, the response is delivered inside
+/// `Error::HandshakeRejected`, so the caller can inspect the status code, headers and body,
+/// for example, telling an authentication failure from an overloaded server.
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    version: String,
+    status: u16,
+    reason: String,
+    // Kept in order, with the casing they were received or added with
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Builds a response with the given status code, and its standard reason phrase,
+    /// to be sent by servers instead of upgrading a connection
+    pub fn new(status: u16) -> Self {
+        HttpResponse {
+            version: String::from("HTTP/1.1"),
+            status,
+            reason: reason_phrase(status).to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Adds a header to the response, which is sent as given, after the ones added before it
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sets the response body, which is sent along with its Content-Length
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    // Serializes the response for sending it, where the connection is always closed afterward
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!("{} {} {}\r\n", self.version, self.status, self.reason);
+        for (name, value) in &self.headers {
+            if !name.eq_ignore_ascii_case("content-length")
+                && !name.eq_ignore_ascii_case("connection")
+            {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        head.push_str(&format!(
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            self.body.len()
+        ));
+
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    // Reads the head and the body within read_timeout each,
+    // or without a timeout, when the caller has its own deadline
+    pub(crate) async fn parse_http_response<T: AsyncReadExt + Unpin>(
+        reader: &mut BufReader<ReadHalf<T>>,
+        read_timeout: Option<Duration>,
+    ) -> Result<HttpResponse, Error> {
+        let buffer = read_http_head(reader, read_timeout).await?;
+
+        let mut response = Self::from_head(&buffer)?;
+        let status = response.status;
+
+        // A successful upgrade has no body, and everything that comes next are websocket frames.
+        // For other responses, we read the body based on Content-Length,
+        // limited to a reasonable size, so it can be delivered to the caller
+        if status != 101 {
+            if let Some(content_length) = response.header("content-length") {
+                let length: usize = content_length
+                    .parse()
+                    .map_err(|_| Error::InvalidContentLength)?;
+                let mut body = vec![0; length.min(MAX_RESPONSE_BODY_SIZE)];
+                let read = reader.read_exact(&mut body);
+                match read_timeout {
+                    Some(read_timeout) => timeout(read_timeout, read).await??,
+                    None => read.await?,
+                };
+                response.body = body;
+            }
+        }
+
+        Ok(response)
+    }
+
+    // Builds the HTTP response from its head, which is the status line and the headers.
+    pub(crate) fn from_head(head: &str) -> Result<HttpResponse, Error> {
+        let header_part = match head.split_once(HTTP_REQUEST_DELIMITER) {
+            Some((header_part, _)) => header_part,
+            None => return Err(Error::HttpParseError),
+        };
+
+        // Parse the status line (e.g., "HTTP/1.1 101 Switching Protocols")
+        let mut lines = header_part.lines();
+        let status_line = lines.next().ok_or(Error::InvalidHTTPStatusLine)?;
+        let mut parts = status_line.splitn(3, ' ');
+        let version = parts.next().ok_or(Error::MissingHTTPVersion)?.to_string();
+        let status = parts
+            .next()
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or(Error::InvalidHTTPStatusLine)?;
+        let reason = parts.next().unwrap_or_default().trim().to_string();
+
+        Ok(HttpResponse {
+            version,
+            status,
+            reason,
+            headers: lines
+                .filter_map(|line| line.split_once(':'))
+                .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+                .collect(),
+            body: Vec::new(),
+        })
+    }
+
+    /// The HTTP version of the response, like `HTTP/1.1`
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// The status code of the response, like `101` or `404`
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// The reason phrase of the response, like `Not Found`
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    /// All the response headers as name and value pairs, in the order they were received
+    /// or added, keeping the casing of their names
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// Returns the value of the first header with the given name,
+    /// where the name is matched case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The response body, which is limited to the first 64 KiB
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// The `Retry-After` header, commonly sent along with 429 and 503 responses.
+    /// Only the delay in seconds format is supported
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.header("retry-after")?
+            .parse::<u64>()
+            .ok()
+            .map(Duration::from_secs)
+    }
+}
+
+// Standard reason phrases, for the status codes servers commonly answer instead of upgrading
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// A read-only view of the HTTP upgrade request that originated a WebSocket connection.
+///
+/// On the server side, this is the request sent by the client, while on the client side
+/// it's the request this library sent to the server.
+/// Besides the request line and headers, it also carries the socket addresses of the connection,
+/// which is useful for routing connections by path, or applying rules based on the client IP,
+/// and the TLS session details, for connections running over TLS.
+#[derive(Debug, Clone)]
+pub struct HandshakeRequest {
+    method: String,
+    uri: String,
+    version: String,
+    headers: HashMap<String, String>,
+    peer_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+    #[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+    tls_info: Option<TlsInfo>,
+    route: Option<RouteMatch>,
+}
+
+impl HandshakeRequest {
+    pub(crate) fn new(
+        request: HttpRequest,
+        peer_addr: Option<SocketAddr>,
+        local_addr: Option<SocketAddr>,
+    ) -> Self {
+        Self {
+            method: request.method,
+            uri: request.uri,
+            version: request.version,
+            headers: request.headers,
+            peer_addr,
+            local_addr,
+            #[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+            tls_info: None,
+            route: None,
+        }
+    }
+
+    #[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+    pub(crate) fn with_tls_info(mut self, tls_info: Option<TlsInfo>) -> Self {
+        self.tls_info = tls_info;
+        self
+    }
+
+    pub(crate) fn set_route(&mut self, route: Option<RouteMatch>) {
+        self.route = route;
+    }
+
+    /// The HTTP method of the request, which is always `GET` for a valid handshake
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// The full request target, including the query string, like `/chat?room=1`
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    /// The HTTP version of the request, like `HTTP/1.1`
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// The path of the request target, without the query string
+    pub fn path(&self) -> &str {
+        self.uri.split_once('?').map_or(&self.uri, |(path, _)| path)
+    }
+
+    /// The raw query string of the request target, without the leading `?`
+    pub fn query(&self) -> Option<&str> {
+        self.uri.split_once('?').map(|(_, query)| query)
+    }
+
+    /// Returns the percent-decoded query parameters of the request.
+    /// If a parameter is repeated, the last value is kept
+    pub fn query_params(&self) -> HashMap<String, String> {
+        url::form_urlencoded::parse(self.query().unwrap_or_default().as_bytes())
+            .into_owned()
+            .collect()
+    }
+
+    /// Returns the percent-decoded value of a single query parameter
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        url::form_urlencoded::parse(self.query()?.as_bytes())
+            .filter(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+            .last()
+    }
+
+    /// All the request headers, keyed by their lowercase name
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    /// Returns the value of a header, where the name is matched case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(String::as_str)
+    }
+
+    /// The `Host` header of the request
+    pub fn host(&self) -> Option<&str> {
+        self.header("host")
+    }
+
+    /// The `Origin` header of the request, mostly sent by browsers
+    pub fn origin(&self) -> Option<&str> {
+        self.header("origin")
+    }
+
+    /// The `User-Agent` header of the request
+    pub fn user_agent(&self) -> Option<&str> {
+        self.header("user-agent")
+    }
+
+    /// The protocols listed in the `Sec-WebSocket-Protocol` header
+    pub fn protocols(&self) -> Vec<&str> {
+        self.header("sec-websocket-protocol")
+            .map(|value| value.split(',').map(str::trim).collect())
+            .unwrap_or_default()
+    }
+
+    /// The address of the remote end of the connection
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// The local address of the connection
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// The TLS session details of the connection, like the peer certificates,
+    /// or `None` if the connection isn't running over TLS
+    #[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+    pub fn tls_info(&self) -> Option<&TlsInfo> {
+        self.tls_info.as_ref()
+    }
+
+    /// The route the connection was dispatched to, when accepted through a `Router`,
+    /// which gives access to the path parameters, like `id` for `/rooms/:id`
+    pub fn route(&self) -> Option<&RouteMatch> {
+        self.route.as_ref()
+    }
 }
@@ -0,0 +1,102 @@
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::{sleep, Duration};
+
+// Delay between starting connection attempts, as recommended by Happy Eyeballs (RFC 8305)
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Custom hostname resolution for clients.
+///
+/// By default, clients resolve hostnames through the operating system resolver.
+/// Implementing this trait, and setting it on `ClientConfig`, allows resolving hostnames
+/// in a different way, like injecting addresses from a service discovery system.
+/// The returned addresses are tried in order, racing IPv6 and IPv4 addresses.
+pub trait Resolver: Debug + Send + Sync {
+    fn resolve(&self, host: &str, port: u16) -> BoxFuture<'static, io::Result<Vec<SocketAddr>>>;
+}
+
+// Resolves the host, with a custom resolver if provided, and connects to the resolved addresses
+pub(crate) async fn connect_tcp(
+    host: &str,
+    port: u16,
+    resolver: Option<&Arc<dyn Resolver>>,
+) -> io::Result<TcpStream> {
+    let addresses = resolve(host, port, resolver).await?;
+    happy_eyeballs(addresses).await
+}
+
+// Resolves the host, with a custom resolver if provided, or the operating system resolver
+pub(crate) async fn resolve(
+    host: &str,
+    port: u16,
+    resolver: Option<&Arc<dyn Resolver>>,
+) -> io::Result<Vec<SocketAddr>> {
+    match resolver {
+        Some(resolver) => resolver.resolve(host, port).await,
+        None => Ok(lookup_host((host, port)).await?.collect()),
+    }
+}
+
+// A simplified Happy Eyeballs (RFC 8305) implementation.
+// Addresses are interleaved by family, and a new connection attempt is started every 250ms,
+// or right after an attempt fails, while the previous attempts keep running.
+// The first connection that succeeds is used, and the remaining ones are dropped
+async fn happy_eyeballs(addresses: Vec<SocketAddr>) -> io::Result<TcpStream> {
+    let mut pending = interleave(addresses);
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+
+    loop {
+        if attempts.is_empty() {
+            match pending.pop_front() {
+                Some(address) => attempts.push(TcpStream::connect(address)),
+                None => {
+                    return Err(last_error.unwrap_or_else(|| {
+                        io::Error::new(io::ErrorKind::NotFound, "no addresses were resolved")
+                    }))
+                }
+            }
+        }
+
+        tokio::select! {
+            Some(result) = attempts.next() => match result {
+                Ok(stream) => return Ok(stream),
+                Err(err) => {
+                    last_error = Some(err);
+                    if let Some(address) = pending.pop_front() {
+                        attempts.push(TcpStream::connect(address));
+                    }
+                }
+            },
+            _ = sleep(CONNECTION_ATTEMPT_DELAY), if !pending.is_empty() => {
+                if let Some(address) = pending.pop_front() {
+                    attempts.push(TcpStream::connect(address));
+                }
+            }
+        }
+    }
+}
+
+// Interleaves IPv6 and IPv4 addresses, starting with the family of the first resolved address
+pub(crate) fn interleave(addresses: Vec<SocketAddr>) -> VecDeque<SocketAddr> {
+    let first_is_ipv6 = addresses.first().is_some_and(SocketAddr::is_ipv6);
+    let (mut preferred, mut other): (VecDeque<_>, VecDeque<_>) = addresses
+        .into_iter()
+        .partition(|address| address.is_ipv6() == first_is_ipv6);
+
+    let mut interleaved = VecDeque::with_capacity(preferred.len() + other.len());
+    loop {
+        match (preferred.pop_front(), other.pop_front()) {
+            (None, None) => break,
+            (first, second) => interleaved.extend(first.into_iter().chain(second)),
+        }
+    }
+    interleaved
+}
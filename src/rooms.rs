@@ -0,0 +1,173 @@
+use crate::error::Error;
+use crate::event::{Event, ID};
+use crate::message::{Message, PreparedMessage};
+use crate::split::WSSender;
+use futures::future::join_all;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::timeout;
+
+const DEFAULT_SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Named groups of connections, for broadcasting messages to all of their members.
+///
+/// Clients are tracked by feeding the server events to `handle_event`, which registers
+/// the new clients, and removes the disconnected ones from all of their rooms.
+/// It's cheap to clone, and all the clones share the same rooms, so it can be moved into
+/// other tasks.
+#[derive(Clone)]
+pub struct Rooms {
+    state: Arc<Mutex<RoomsState>>,
+    send_timeout: Duration,
+}
+
+impl Default for Rooms {
+    fn default() -> Self {
+        Self {
+            state: Arc::default(),
+            send_timeout: DEFAULT_SEND_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Default)]
+struct RoomsState {
+    clients: HashMap<ID, WSSender>,
+    rooms: HashMap<String, HashSet<ID>>,
+}
+
+impl Rooms {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long a broadcast waits for each member, 5 seconds by default.
+    /// Members that don't take the message in time are returned as failed, with
+    /// `Error::Timeout`, although their message is still sent in the background
+    pub fn send_timeout(mut self, send_timeout: Duration) -> Self {
+        self.send_timeout = send_timeout;
+        self
+    }
+
+    /// Keeps track of the clients from the server events, registering them on
+    /// `Event::NewClient`, and removing them from all rooms on `Event::Disconnect`
+    pub fn handle_event(&self, event: &Event) {
+        match event {
+            Event::NewClient(id, writer, _) => self.add_client(*id, writer.sender()),
+            Event::Disconnect(id) => self.remove_client(id),
+            _ => {}
+        }
+    }
+
+    /// Registers a client, for connections that aren't accepted by the built-in server
+    pub fn add_client(&self, id: ID, sender: WSSender) {
+        self.state.lock().unwrap().clients.insert(id, sender);
+    }
+
+    /// Removes a client, along with its membership in all rooms
+    pub fn remove_client(&self, id: &ID) {
+        let mut state = self.state.lock().unwrap();
+        state.clients.remove(id);
+        state.rooms.retain(|_, members| {
+            members.remove(id);
+            !members.is_empty()
+        });
+    }
+
+    /// Adds a registered client to a room, which is created if it doesn't exist.
+    /// Unknown clients are ignored
+    pub fn join(&self, room: &str, id: ID) {
+        let mut state = self.state.lock().unwrap();
+        if state.clients.contains_key(&id) {
+            state.rooms.entry(room.to_string()).or_default().insert(id);
+        }
+    }
+
+    /// Removes a client from a room, which is dropped once it has no members left
+    pub fn leave(&self, room: &str, id: &ID) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(members) = state.rooms.get_mut(room) {
+            members.remove(id);
+            if members.is_empty() {
+                state.rooms.remove(room);
+            }
+        }
+    }
+
+    /// The members of a room
+    pub fn members(&self, room: &str) -> Vec<ID> {
+        let state = self.state.lock().unwrap();
+        state
+            .rooms
+            .get(room)
+            .map(|members| members.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// The rooms a client is a member of
+    pub fn rooms_of(&self, id: &ID) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .rooms
+            .iter()
+            .filter(|(_, members)| members.contains(id))
+            .map(|(room, _)| room.clone())
+            .collect()
+    }
+
+    /// Sends a message to all members of a room.
+    ///
+    /// The message is encoded only once, as a `PreparedMessage`, and sent to all of them
+    /// concurrently, so a slow member doesn't hold up the others.
+    /// It resolves once every send is over, or timed out, returning the ones that failed.
+    pub async fn broadcast(&self, room: &str, message: Message) -> Vec<(ID, Error)> {
+        let recipients = {
+            let state = self.state.lock().unwrap();
+            match state.rooms.get(room) {
+                Some(members) => members
+                    .iter()
+                    .filter_map(|id| Some((*id, state.clients.get(id)?.clone())))
+                    .collect(),
+                None => Vec::new(),
+            }
+        };
+        send_all(recipients, message, self.send_timeout).await
+    }
+
+    /// Sends a message to all registered clients, in any room or none
+    pub async fn broadcast_all(&self, message: Message) -> Vec<(ID, Error)> {
+        let recipients = {
+            let state = self.state.lock().unwrap();
+            state
+                .clients
+                .iter()
+                .map(|(id, sender)| (*id, sender.clone()))
+                .collect()
+        };
+        send_all(recipients, message, self.send_timeout).await
+    }
+}
+
+// Sends the message to every recipient in its own task, so a send that times out is left running,
+// instead of being cancelled halfway through a frame, which would break the connection
+async fn send_all(
+    recipients: Vec<(ID, WSSender)>,
+    message: Message,
+    send_timeout: Duration,
+) -> Vec<(ID, Error)> {
+    let prepared = PreparedMessage::new(message);
+    let sends = recipients.into_iter().map(|(id, sender)| {
+        let prepared = prepared.clone();
+        let send = tokio::spawn(async move { sender.send_prepared(&prepared).await });
+        async move {
+            let sent = match timeout(send_timeout, send).await {
+                Ok(Ok(sent)) => sent,
+                Ok(Err(join_error)) => Err(Error::from(join_error)),
+                Err(elapsed) => Err(Error::from(elapsed)),
+            };
+            sent.err().map(|err| (id, err))
+        }
+    });
+    join_all(sends).await.into_iter().flatten().collect()
+}
@@ -0,0 +1,174 @@
+use crate::config::WebSocketConfig;
+use crate::request::HandshakeRequest;
+use percent_encoding::percent_decode_str;
+use std::collections::HashMap;
+
+/// Dispatches handshake requests to routes, by their path, and optionally their `Host` header.
+///
+/// Routes are matched in the order they were added, and the first one that matches is used.
+/// Each route can have its own `WebSocketConfig` and subprotocols, and requests matching no route
+/// are answered with 404 Not Found, before the upgrade.
+/// The matched route, along with its path parameters, is available through
+/// `HandshakeRequest::route`.
+#[derive(Debug, Clone, Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a route, after the existing ones
+    pub fn route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    // Finds the first route matching the request, along with its path parameters
+    pub(crate) fn find(&self, request: &HandshakeRequest) -> Option<(&Route, RouteMatch)> {
+        self.routes.iter().find_map(|route| {
+            let params = route.matches(request)?;
+            let protocol = route.select_protocol(request);
+            Some((
+                route,
+                RouteMatch {
+                    pattern: route.pattern.clone(),
+                    params,
+                    protocol,
+                },
+            ))
+        })
+    }
+}
+
+/// A route of a `Router`.
+///
+/// Its path pattern is split in segments, where a segment starting with `:`, like in
+/// `/rooms/:id`, matches any single segment, captured as a path parameter under that name.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pattern: String,
+    host: Option<String>,
+    config: Option<WebSocketConfig>,
+    protocols: Vec<String>,
+}
+
+impl Route {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            host: None,
+            config: None,
+            protocols: Vec::new(),
+        }
+    }
+
+    /// Only matches requests for this host, compared case-insensitively,
+    /// and ignoring the port of the `Host` header
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = Some(host.to_lowercase());
+        self
+    }
+
+    /// The configuration of the connections of this route.
+    /// Without it, the configuration given to the server is used
+    pub fn config(mut self, config: WebSocketConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// The subprotocols supported by this route.
+    /// The client's order of preference is followed, so the first protocol offered by the client,
+    /// that the route supports, is selected, regardless of its position in this list
+    pub fn protocols<I, S>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.protocols = protocols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub(crate) fn web_socket_config(&self) -> Option<&WebSocketConfig> {
+        self.config.as_ref()
+    }
+
+    // Returns the path parameters, if the route matches the request
+    fn matches(&self, request: &HandshakeRequest) -> Option<HashMap<String, String>> {
+        if let Some(host) = &self.host {
+            let request_host = request.host()?;
+            if !strip_port(request_host).eq_ignore_ascii_case(host) {
+                return None;
+            }
+        }
+
+        let mut pattern_segments = self.pattern.split('/');
+        let mut path_segments = request.path().split('/');
+        let mut params = HashMap::new();
+        loop {
+            match (pattern_segments.next(), path_segments.next()) {
+                (None, None) => return Some(params),
+                (Some(pattern), Some(segment)) => match pattern.strip_prefix(':') {
+                    Some(name) if !segment.is_empty() => {
+                        let value = percent_decode_str(segment).decode_utf8().ok()?;
+                        params.insert(name.to_string(), value.into_owned());
+                    }
+                    Some(_) => return None,
+                    None if pattern == segment => {}
+                    None => return None,
+                },
+                _ => return None,
+            }
+        }
+    }
+
+    fn select_protocol(&self, request: &HandshakeRequest) -> Option<String> {
+        request
+            .protocols()
+            .into_iter()
+            .find(|offered| self.protocols.iter().any(|protocol| protocol == offered))
+            .map(str::to_string)
+    }
+}
+
+/// The route a connection was dispatched to by a `Router`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteMatch {
+    pattern: String,
+    params: HashMap<String, String>,
+    protocol: Option<String>,
+}
+
+impl RouteMatch {
+    /// The path pattern of the route, like `/rooms/:id`
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// All the percent-decoded path parameters
+    pub fn params(&self) -> &HashMap<String, String> {
+        &self.params
+    }
+
+    /// Returns a single path parameter, like `id` for `/rooms/:id`
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    /// The subprotocol selected for the connection, sent back in `Sec-WebSocket-Protocol`
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+}
+
+// Removes the port from a Host header value, keeping IPv6 addresses between brackets
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host
+            .split_once(']')
+            .map_or(host, |(address, _)| &host[..address.len() + 1]);
+    }
+    host.split_once(':').map_or(host, |(name, _)| name)
+}
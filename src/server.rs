@@ -1,12 +1,1003 @@
-use crate::config::ServerConfig;
-use crate::event::{generate_new_uuid, Event, EventStream};
-use crate::handshake::accept_async_with_config;
+use crate::config::{ServerConfig, WebSocketConfig};
+use crate::connection::WSConnection;
+use crate::error::Error as WsError;
+use crate::event::{generate_new_uuid, Event, EventStream, Rejection, ID};
+use crate::event_queue::{event_queue, EventReceiver, EventSender, SendError};
+use crate::frame::is_valid_close_code;
+use crate::handler::{handle_client, Handler};
+use crate::handshake::{accept_server, respond_without_upgrade};
+use crate::http::HttpHandler;
+use crate::message::Message;
+use crate::request::{HandshakeRequest, HttpResponse};
+use crate::router::Router;
+use crate::split::{WSReader, WSSender, WSWriter};
 use crate::stream::SocketFlowStream;
-use futures::StreamExt;
+use crate::tls::ServerTlsAcceptor;
+use futures::future::join_all;
+use futures::{Stream, StreamExt};
+use log::{info, warn};
+use std::collections::HashMap;
 use std::io::Error;
-use tokio::net::TcpListener;
-use tokio::sync::mpsc;
-use tokio_rustls::{TlsAcceptor, TlsStream};
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch, Notify, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::{sleep, timeout};
+use tokio_stream::wrappers::ReceiverStream;
+
+// Status code sent to clients when the server is going down, per the WebSockets RFC
+const CLOSE_GOING_AWAY: u16 = 1001;
+const SHUTDOWN_REASON: &str = "server shutting down";
+// Time given to each connection left open after the shutdown deadline for releasing its writer,
+// which a write blocked on a client that stopped reading keeps held
+const FORCE_CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+// Sent to clients closed by the DisconnectClient policy of the event channel
+const CLOSE_TRY_AGAIN_LATER: u16 = 1013;
+const OVERLOADED_REASON: &str = "server overloaded";
+const DEFAULT_EVENT_CHANNEL_CAPACITY: usize = 1000;
+// Messages buffered for each client handed over in per-client mode,
+// the same as the connections opened through accept_async
+const CLIENT_READ_BUFFER: usize = 20;
+const DEFAULT_MAX_CONCURRENT_HANDSHAKES: usize = 1024;
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Sent in the Retry-After header of rejected connections
+const RETRY_AFTER_SECONDS: u64 = 5;
+// Deadline for answering a rejected connection, which is short, since it doesn't count against
+// the concurrent handshakes
+const REJECTION_TIMEOUT: Duration = Duration::from_secs(1);
+// Rejected connections answered at the same time, past which they are dropped without an answer,
+// so a flood of rejections can't pile up tasks and sockets
+const MAX_PENDING_REJECTIONS: usize = 64;
+// Accept errors, like running out of file descriptors, are usually persistent for a while,
+// so the accept loop backs off exponentially between these bounds, instead of spinning
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+// Where a listener of the ServerBuilder comes from
+enum ListenerSource {
+    Address(SocketAddr),
+    Listener(TcpListener),
+}
+
+struct ListenerSpec {
+    source: ListenerSource,
+    tls: bool,
+}
+
+/// Builds a websockets server listening on one or more addresses.
+///
+/// Unlike `start_server_with_config`, which always binds `0.0.0.0:{port}`, each listener
+/// can be bound to any address, like loopback or IPv6 ones, or be an already bound
+/// `TcpListener`, and TLS is enabled per listener, so plain and TLS listeners can be served at once.
+/// All listeners share the same `EventStream`.
+#[derive(Default)]
+pub struct ServerBuilder {
+    config: ServerConfig,
+    listeners: Vec<ListenerSpec>,
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the websockets and TLS configuration, shared by all listeners
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Adds a plain listener, bound to the given address.
+    /// Binding port 0 picks a free port, which is reported by `Server::local_addrs`
+    pub fn bind(self, addr: SocketAddr) -> Self {
+        self.add(ListenerSource::Address(addr), false)
+    }
+
+    /// Adds a TLS listener, bound to the given address, using the TLS setup of the `ServerConfig`
+    pub fn bind_tls(self, addr: SocketAddr) -> Self {
+        self.add(ListenerSource::Address(addr), true)
+    }
+
+    /// Adds a plain listener, from an already bound `TcpListener`
+    pub fn listener(self, listener: TcpListener) -> Self {
+        self.add(ListenerSource::Listener(listener), false)
+    }
+
+    /// Adds a TLS listener, from an already bound `TcpListener`,
+    /// using the TLS setup of the `ServerConfig`
+    pub fn listener_tls(self, listener: TcpListener) -> Self {
+        self.add(ListenerSource::Listener(listener), true)
+    }
+
+    fn add(mut self, source: ListenerSource, tls: bool) -> Self {
+        self.listeners.push(ListenerSpec { source, tls });
+        self
+    }
+
+    /// Binds all the listeners, and starts accepting connections on them.
+    ///
+    /// Fails if any address can't be bound, if there are no listeners, or if a TLS listener
+    /// was added without a TLS setup in the `ServerConfig`.
+    pub async fn start(self) -> Result<Server, WsError> {
+        let (config, listeners, local_addrs) = self.bind_listeners().await?;
+
+        let (tx, rx) = server_event_queue(&config);
+        let state = Arc::new(ServerState::new(&config));
+        for (listener, acceptor) in listeners {
+            spawn_accept_loop(
+                listener,
+                acceptor,
+                Delivery::Events(tx.clone()),
+                state.clone(),
+            );
+        }
+
+        Ok(Server {
+            local_addrs,
+            events: EventStream::from_queue(rx),
+            handle: ServerHandle { state },
+        })
+    }
+
+    /// Binds all the listeners, and starts accepting connections on them, in per-client mode.
+    ///
+    /// Instead of a single stream of events for all connections, each client is handed over,
+    /// once upgraded, as a `ClientConnection`, with its own reader and writer, so it can be
+    /// handled in its own task, without being held up by the other clients.
+    /// Handshake failures and rejected connections are only logged.
+    /// Fails like `start`.
+    pub async fn start_per_client(self) -> Result<ClientStream, WsError> {
+        let (config, listeners, local_addrs) = self.bind_listeners().await?;
+
+        let (tx, rx) = mpsc::channel(
+            config
+                .event_channel_capacity
+                .unwrap_or(DEFAULT_EVENT_CHANNEL_CAPACITY)
+                .max(1),
+        );
+        let state = Arc::new(ServerState::new(&config));
+        for (listener, acceptor) in listeners {
+            spawn_accept_loop(
+                listener,
+                acceptor,
+                Delivery::Clients(tx.clone()),
+                state.clone(),
+            );
+        }
+
+        Ok(ClientStream {
+            local_addrs,
+            clients: rx,
+            handle: ServerHandle { state },
+        })
+    }
+
+    #[allow(clippy::type_complexity)]
+    async fn bind_listeners(
+        self,
+    ) -> Result<
+        (
+            ServerConfig,
+            Vec<(TcpListener, Option<ServerTlsAcceptor>)>,
+            Vec<SocketAddr>,
+        ),
+        WsError,
+    > {
+        if self.listeners.is_empty() {
+            return Err(WsError::NoListeners);
+        }
+
+        let tls_acceptor = ServerTlsAcceptor::from_config(&self.config);
+        // Binding everything before spawning any accept loop, so a failure doesn't leave
+        // a half started server behind
+        let mut listeners = Vec::with_capacity(self.listeners.len());
+        for spec in self.listeners {
+            let acceptor = match (spec.tls, &tls_acceptor) {
+                (false, _) => None,
+                (true, Some(acceptor)) => Some(acceptor.clone()),
+                (true, None) => {
+                    return Err(WsError::TlsConfigError(String::from(
+                        "TLS listeners require a TLS setup in the ServerConfig",
+                    )))
+                }
+            };
+            let listener = match spec.source {
+                ListenerSource::Address(addr) => TcpListener::bind(addr).await?,
+                ListenerSource::Listener(listener) => listener,
+            };
+            listeners.push((listener, acceptor));
+        }
+
+        let local_addrs = listeners
+            .iter()
+            .map(|(listener, _)| listener.local_addr())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok((self.config, listeners, local_addrs))
+    }
+}
+
+/// A running websockets server, started by `ServerBuilder`.
+///
+/// It's a stream of the server events, like `EventStream`, and reports the addresses
+/// its listeners are actually bound to.
+/// Dropping it, or the `EventStream` returned by `into_event_stream`, shuts the server down.
+pub struct Server {
+    local_addrs: Vec<SocketAddr>,
+    events: EventStream,
+    handle: ServerHandle,
+}
+
+impl Server {
+    /// The addresses of all listeners, in the order they were added to the builder
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// The address of the first listener
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addrs[0]
+    }
+
+    /// Returns a handle for controlling this server, like shutting it down,
+    /// which can be cloned and moved into other tasks
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+
+    /// Returns the EventStream of this server, dropping the addresses
+    pub fn into_event_stream(self) -> EventStream {
+        self.events
+    }
+}
+
+impl Stream for Server {
+    type Item = Event;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        Pin::new(&mut this.events).poll_next(cx)
+    }
+}
+
+/// A running websockets server in per-client mode, started by `ServerBuilder::start_per_client`.
+///
+/// It's a stream of the upgraded clients, and reports the addresses its listeners are
+/// actually bound to. Dropping it shuts the server down.
+pub struct ClientStream {
+    local_addrs: Vec<SocketAddr>,
+    clients: mpsc::Receiver<ClientConnection>,
+    handle: ServerHandle,
+}
+
+impl ClientStream {
+    /// The addresses of all listeners, in the order they were added to the builder
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// The address of the first listener
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addrs[0]
+    }
+
+    /// Returns a handle for controlling this server, like shutting it down,
+    /// which can be cloned and moved into other tasks
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+
+    /// Runs the handler over every client, each one in its own task.
+    ///
+    /// It resolves once the server is shut down, and all of its connections are over.
+    pub async fn serve<H: Handler>(mut self, handler: H) {
+        let handler = Arc::new(handler);
+        let mut connections = JoinSet::new();
+        loop {
+            tokio::select! {
+                client = self.next() => match client {
+                    Some(client) => {
+                        connections.spawn(handle_client(handler.clone(), client));
+                    }
+                    None => break,
+                },
+                // Reaping the finished connections as they go
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            }
+        }
+        while connections.join_next().await.is_some() {}
+    }
+}
+
+impl Stream for ClientStream {
+    type Item = ClientConnection;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().clients.poll_recv(cx)
+    }
+}
+
+/// A client upgraded by a server in per-client mode, with its connection and details.
+///
+/// The connection is still tracked by the `ServerHandle`, until it's closed.
+pub struct ClientConnection {
+    info: ClientInfo,
+    connection: WSConnection,
+}
+
+impl ClientConnection {
+    /// The ID of the client
+    pub fn id(&self) -> ID {
+        self.info.id
+    }
+
+    /// The details of the client, like its address and when it connected
+    pub fn info(&self) -> &ClientInfo {
+        &self.info
+    }
+
+    /// The connection, for reading and sending messages
+    pub fn connection(&mut self) -> &mut WSConnection {
+        &mut self.connection
+    }
+
+    /// Takes the client details and its connection
+    pub fn into_parts(self) -> (ClientInfo, WSConnection) {
+        (self.info, self.connection)
+    }
+}
+
+/// A cloneable handle for controlling a running `Server`, and its clients
+#[derive(Clone)]
+pub struct ServerHandle {
+    state: Arc<ServerState>,
+}
+
+impl ServerHandle {
+    /// Lists the clients currently connected, in no particular order
+    pub fn clients(&self) -> Vec<ClientInfo> {
+        self.state
+            .connections
+            .lock()
+            .unwrap()
+            .values()
+            .map(|client| client.info.clone())
+            .collect()
+    }
+
+    /// Returns the details of a connected client, or `None` if there is no client with this ID
+    pub fn client(&self, id: &ID) -> Option<ClientInfo> {
+        self.state
+            .connections
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|client| client.info.clone())
+    }
+
+    /// Sends a message to a single client, by its ID
+    pub async fn send_to(&self, id: &ID, message: Message) -> Result<(), WsError> {
+        self.state.sender(id)?.send_message(message).await
+    }
+
+    /// Disconnects a client, by its ID, starting the closing handshake with the given
+    /// status code and reason.
+    /// The client is reported with `Event::Disconnect` once the connection is closed.
+    /// Codes that can't be sent, like 1005 and 1006, or codes below 1000, are refused with
+    /// `Error::InvalidCloseCode`
+    pub async fn disconnect(&self, id: &ID, code: u16, reason: &str) -> Result<(), WsError> {
+        if !is_valid_close_code(code) {
+            return Err(WsError::InvalidCloseCode(code));
+        }
+        self.state.sender(id)?.close(code, reason).await
+    }
+
+    /// Gracefully shuts down the server.
+    ///
+    /// The listeners stop accepting connections, and every live connection is sent a Close
+    /// frame with status 1001 (going away). It then waits for the clients to complete the closing
+    /// handshakes, up to the given deadline. Connections still open once it passes are dropped,
+    /// without waiting for their closing handshakes.
+    pub async fn shutdown(&self, deadline: Duration) {
+        self.state.shutdown.send_replace(true);
+        if let Some(http_handler) = &self.state.http_handler {
+            http_handler.on_shutdown();
+        }
+
+        let drain = async {
+            let senders = self.state.senders();
+            join_all(
+                senders
+                    .iter()
+                    .map(|sender| sender.close(CLOSE_GOING_AWAY, SHUTDOWN_REASON)),
+            )
+            .await;
+            self.state.drained().await;
+        };
+        if timeout(deadline, drain).await.is_err() {
+            warn!(
+                "Server shutdown deadline elapsed with {} connections still open, closing them",
+                self.state.connections.lock().unwrap().len()
+            );
+            let senders = self.state.senders();
+            join_all(
+                senders
+                    .iter()
+                    .map(|sender| timeout(FORCE_CLOSE_TIMEOUT, sender.force_close())),
+            )
+            .await;
+        }
+    }
+
+    /// Whether `shutdown` was called
+    pub fn is_shutting_down(&self) -> bool {
+        *self.state.shutdown.borrow()
+    }
+}
+
+/// The details of a client connected to the server
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    id: ID,
+    peer_addr: SocketAddr,
+    connected_at: SystemTime,
+    path: String,
+}
+
+impl ClientInfo {
+    /// The ID of the client, the same reported in its events
+    pub fn id(&self) -> ID {
+        self.id
+    }
+
+    /// The address of the client
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// When the client completed the handshake
+    pub fn connected_at(&self) -> SystemTime {
+        self.connected_at
+    }
+
+    /// The path requested by the client in the handshake
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+// A live connection, tracked by the server
+struct Client {
+    sender: WSSender,
+    info: ClientInfo,
+}
+
+// State shared between the accept loops, the connection tasks and the ServerHandle
+struct ServerState {
+    web_socket_config: Option<WebSocketConfig>,
+    router: Option<Router>,
+    http_handler: Option<Arc<dyn HttpHandler>>,
+    // Limits the handshakes running at the same time
+    handshakes: Arc<Semaphore>,
+    // Limits the rejected connections being answered at the same time
+    rejections: Arc<Semaphore>,
+    handshake_timeout: Duration,
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    admissions: std::sync::Mutex<Admissions>,
+    shutdown: watch::Sender<bool>,
+    // All live connections
+    connections: std::sync::Mutex<HashMap<ID, Client>>,
+    // Notified every time a connection is closed
+    closed: Notify,
+}
+
+impl ServerState {
+    fn new(config: &ServerConfig) -> Self {
+        Self {
+            web_socket_config: config.web_socket_config.clone(),
+            router: config.router.clone(),
+            http_handler: config.http_handler.clone(),
+            handshakes: Arc::new(Semaphore::new(
+                config
+                    .max_concurrent_handshakes
+                    .unwrap_or(DEFAULT_MAX_CONCURRENT_HANDSHAKES)
+                    .max(1),
+            )),
+            rejections: Arc::new(Semaphore::new(MAX_PENDING_REJECTIONS)),
+            handshake_timeout: config
+                .handshake_timeout
+                .unwrap_or(DEFAULT_HANDSHAKE_TIMEOUT),
+            max_connections: config.max_connections,
+            max_connections_per_ip: config.max_connections_per_ip,
+            admissions: std::sync::Mutex::new(Admissions::default()),
+            shutdown: watch::Sender::new(false),
+            connections: std::sync::Mutex::new(HashMap::new()),
+            closed: Notify::new(),
+        }
+    }
+
+    // Admits a new connection, unless it exceeds the connection limits, or the consumer of
+    // the events is falling behind.
+    // The connection counts until the returned slot is dropped
+    fn admit(
+        self: &Arc<Self>,
+        peer_addr: SocketAddr,
+        delivery: &Delivery,
+    ) -> Result<ConnectionSlot, Rejection> {
+        if delivery.is_full() {
+            return Err(Rejection::Overloaded);
+        }
+
+        let ip = peer_addr.ip();
+        let mut admissions = self.admissions.lock().unwrap();
+        if self
+            .max_connections
+            .is_some_and(|max| admissions.total >= max)
+        {
+            return Err(Rejection::MaxConnections);
+        }
+        let from_ip = admissions.per_ip.get(&ip).copied().unwrap_or_default();
+        if self
+            .max_connections_per_ip
+            .is_some_and(|max| from_ip >= max)
+        {
+            return Err(Rejection::MaxConnectionsPerIp(ip));
+        }
+
+        admissions.total += 1;
+        admissions.per_ip.insert(ip, from_ip + 1);
+        Ok(ConnectionSlot {
+            state: self.clone(),
+            peer_addr,
+        })
+    }
+
+    async fn register(&self, sender: WSSender, info: ClientInfo) {
+        self.connections.lock().unwrap().insert(
+            info.id,
+            Client {
+                sender: sender.clone(),
+                info,
+            },
+        );
+        // A connection that completed its handshake while the server was shutting down
+        // may have been missed by shutdown, so it's closed right away
+        if *self.shutdown.borrow() {
+            let _ = sender.close(CLOSE_GOING_AWAY, SHUTDOWN_REASON).await;
+        }
+    }
+
+    // Shuts the server down once the EventStream, or the ClientStream, is dropped, since nobody would handle
+    // its events anymore
+    async fn abandon(&self) {
+        if self.shutdown.send_replace(true) {
+            return;
+        }
+
+        warn!("Server stream dropped, shutting down the server");
+        let senders = self.senders();
+        join_all(
+            senders
+                .iter()
+                .map(|sender| sender.close(CLOSE_GOING_AWAY, SHUTDOWN_REASON)),
+        )
+        .await;
+    }
+
+    fn remove(&self, id: &ID) {
+        self.connections.lock().unwrap().remove(id);
+        self.closed.notify_waiters();
+    }
+
+    fn senders(&self) -> Vec<WSSender> {
+        self.connections
+            .lock()
+            .unwrap()
+            .values()
+            .map(|client| client.sender.clone())
+            .collect()
+    }
+
+    fn sender(&self, id: &ID) -> Result<WSSender, WsError> {
+        self.connections
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|client| client.sender.clone())
+            .ok_or(WsError::UnknownClient(*id))
+    }
+
+    // Resolves once all connections are closed
+    async fn drained(&self) {
+        loop {
+            let closed = self.closed.notified();
+            tokio::pin!(closed);
+            // Registering for notifications before checking, for not missing a close
+            // that happens in between
+            closed.as_mut().enable();
+            if self.connections.lock().unwrap().is_empty() {
+                return;
+            }
+            closed.await;
+        }
+    }
+}
+
+// Counts the connections held by the server, for enforcing the connection limits
+#[derive(Default)]
+struct Admissions {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+// An admitted connection, which is released from the counts when dropped
+struct ConnectionSlot {
+    state: Arc<ServerState>,
+    peer_addr: SocketAddr,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut admissions = self.state.admissions.lock().unwrap();
+        admissions.total -= 1;
+        let ip = self.peer_addr.ip();
+        if let Some(count) = admissions.per_ip.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                admissions.per_ip.remove(&ip);
+            }
+        }
+    }
+}
+
+// Creates the queue delivering the server events to its EventStream
+fn server_event_queue(config: &ServerConfig) -> (EventSender, EventReceiver) {
+    event_queue(
+        config
+            .event_channel_capacity
+            .unwrap_or(DEFAULT_EVENT_CHANNEL_CAPACITY),
+        config.event_channel_policy,
+    )
+}
+
+// Where the server delivers its connections, either as events of a single EventStream,
+// or as ClientConnections, each one with its own reader, in per-client mode
+#[derive(Clone)]
+enum Delivery {
+    Events(EventSender),
+    Clients(mpsc::Sender<ClientConnection>),
+}
+
+impl Delivery {
+    fn is_full(&self) -> bool {
+        match self {
+            Delivery::Events(tx) => tx.is_full(),
+            Delivery::Clients(tx) => tx.capacity() == 0,
+        }
+    }
+
+    // Resolves once the stream of the server is dropped
+    async fn closed(&self) {
+        match self {
+            Delivery::Events(tx) => tx.closed().await,
+            Delivery::Clients(tx) => tx.closed().await,
+        }
+    }
+}
+
+// Reports an event, shutting the server down if the EventStream was dropped.
+// In per-client mode, there are no events, so failures and rejections are logged instead
+async fn report(delivery: &Delivery, state: &ServerState, event: Event) -> Result<(), SendError> {
+    let tx = match delivery {
+        Delivery::Events(tx) => tx,
+        Delivery::Clients(_) => {
+            log_event(event);
+            return Ok(());
+        }
+    };
+
+    let result = tx.send(event).await;
+    if result == Err(SendError::Closed) {
+        state.abandon().await;
+    }
+    result
+}
+
+// Reports an event without waiting for room in the queue, dropping it when the queue is full,
+// for the rejections of the accept loop, which can't be held up by a slow consumer
+fn try_report(delivery: &Delivery, event: Event) {
+    match delivery {
+        // A dropped EventStream is noticed by the accept loop itself
+        Delivery::Events(tx) => {
+            let _ = tx.try_send(event);
+        }
+        Delivery::Clients(_) => log_event(event),
+    }
+}
+
+// In per-client mode, there are no events, so failures and rejections are logged instead
+fn log_event(event: Event) {
+    match event {
+        Event::Error(id, err) => warn!("Connection {} failed: {}", id, err),
+        Event::Rejected(id, peer_addr, rejection) => {
+            info!(
+                "Rejected connection {} from {}: {:?}",
+                id, peer_addr, rejection
+            )
+        }
+        _ => {}
+    }
+}
+
+// Spawns the task accepting connections from a single listener, and reporting
+// their events through tx
+fn spawn_accept_loop(
+    listener: TcpListener,
+    tls_acceptor: Option<ServerTlsAcceptor>,
+    delivery: Delivery,
+    state: Arc<ServerState>,
+) {
+    // This spawned task will be used for accepting new connections, while each one of them
+    // runs its handshakes, messages, disconnections and errors in its own task, concurrently.
+    tokio::spawn(async move {
+        let mut shutdown = state.shutdown.subscribe();
+        let mut backoff = MIN_ACCEPT_BACKOFF;
+        loop {
+            // we are using UUID, which is more flexible, and secure than incrementing IDs
+            let uuid = generate_new_uuid();
+            // Once the server is shutting down, the listener is dropped, refusing new connections
+            let accepted = tokio::select! {
+                _ = shutdown.wait_for(|shutting_down| *shutting_down) => break,
+                _ = delivery.closed() => break,
+                accepted = accept_with_permit(&listener, &state) => accepted,
+            };
+            match accepted {
+                Ok((stream, peer_addr, permit)) => {
+                    backoff = MIN_ACCEPT_BACKOFF;
+                    match state.admit(peer_addr, &delivery) {
+                        Ok(slot) => {
+                            tokio::spawn(handle_connection(
+                                uuid,
+                                stream,
+                                tls_acceptor.clone(),
+                                permit,
+                                slot,
+                                delivery.clone(),
+                                state.clone(),
+                            ));
+                        }
+                        Err(rejection) => {
+                            // Rejected connections don't hold a handshake slot, otherwise
+                            // the rejected clients could starve the admitted ones
+                            drop(permit);
+                            try_report(&delivery, Event::Rejected(uuid, peer_addr, rejection));
+                            reject_connection(stream, tls_acceptor.clone(), &state);
+                        }
+                    }
+                }
+                Err(error) => {
+                    let _ = report(&delivery, &state, Event::Error(uuid, error.into())).await;
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                }
+            }
+        }
+        // Unless the server is already shutting down, the loop ended because
+        // the EventStream was dropped
+        state.abandon().await;
+    });
+}
+
+// Waits for a free handshake slot before accepting a connection, so when the maximum
+// of concurrent handshakes is reached, new connections queue up in the listener backlog
+async fn accept_with_permit(
+    listener: &TcpListener,
+    state: &ServerState,
+) -> Result<(TcpStream, SocketAddr, OwnedSemaphorePermit), Error> {
+    let permit = state
+        .handshakes
+        .clone()
+        .acquire_owned()
+        .await
+        .map_err(Error::other)?;
+    let (stream, peer_addr) = listener.accept().await?;
+    Ok((stream, peer_addr, permit))
+}
+
+async fn accept_tls(
+    stream: TcpStream,
+    tls_acceptor: Option<&ServerTlsAcceptor>,
+) -> Result<SocketFlowStream, WsError> {
+    match tls_acceptor {
+        Some(acceptor) => acceptor.accept(stream).await,
+        None => Ok(SocketFlowStream::Plain(stream)),
+    }
+}
+
+// Answers a refused connection with 503 and Retry-After, instead of upgrading it.
+// Answering takes a task of its own, since it may need a TLS handshake, but only up to
+// MAX_PENDING_REJECTIONS of them, past which the connection is dropped right away
+fn reject_connection(
+    stream: TcpStream,
+    tls_acceptor: Option<ServerTlsAcceptor>,
+    state: &ServerState,
+) {
+    let Ok(permit) = state.rejections.clone().try_acquire_owned() else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let response =
+            HttpResponse::new(503).with_header("Retry-After", &RETRY_AFTER_SECONDS.to_string());
+        let respond = async {
+            let socket_stream = accept_tls(stream, tls_acceptor.as_ref()).await?;
+            respond_without_upgrade(socket_stream, &response).await
+        };
+        // Answering is best effort, since the client may not even send its request
+        let _ = timeout(REJECTION_TIMEOUT, respond).await;
+        drop(permit);
+    });
+}
+
+// Runs the TLS and WebSocket handshakes of an accepted connection, within the handshake deadline,
+// and then delivers it, tracking it until it's closed.
+// Only fully upgraded connections are reported as new clients
+async fn handle_connection(
+    uuid: ID,
+    stream: TcpStream,
+    tls_acceptor: Option<ServerTlsAcceptor>,
+    permit: OwnedSemaphorePermit,
+    slot: ConnectionSlot,
+    delivery: Delivery,
+    state: Arc<ServerState>,
+) {
+    let handshake = async {
+        let socket_stream = accept_tls(stream, tls_acceptor.as_ref()).await?;
+        accept_server(
+            socket_stream,
+            state.web_socket_config.clone(),
+            state.router.as_ref(),
+            state.http_handler.as_deref(),
+        )
+        .await
+    };
+    let result = match timeout(state.handshake_timeout, handshake).await {
+        Ok(result) => result,
+        Err(elapsed) => Err(elapsed.into()),
+    };
+    // The handshake slot is released as soon as the handshake is over
+    drop(permit);
+
+    let ws_connection = match result {
+        Ok(Some(conn)) => conn,
+        // Plain HTTP requests were answered by the HttpHandler, and aren't reported
+        Ok(None) => return,
+        Err(err) => {
+            let _ = report(&delivery, &state, Event::Error(uuid, err)).await;
+            return;
+        }
+    };
+    let request = ws_connection.request().clone();
+    // splitting the connection, so we could monitor incoming messages,
+    // and handover the writer to the end-user
+    let (ws_reader, ws_writer) = ws_connection.split();
+    let info = ClientInfo {
+        id: uuid,
+        peer_addr: slot.peer_addr,
+        connected_at: SystemTime::now(),
+        path: request.path().to_string(),
+    };
+    state.register(ws_writer.sender(), info.clone()).await;
+
+    match &delivery {
+        Delivery::Events(tx) => {
+            deliver_events(uuid, ws_reader, ws_writer, request, tx, &state).await;
+            // send disconnect event when connection closed
+            state.remove(&uuid);
+            let _ = report(&delivery, &state, Event::Disconnect(uuid)).await;
+        }
+        Delivery::Clients(tx) => {
+            deliver_client(info, ws_reader, ws_writer, request, tx, &state).await;
+            state.remove(&uuid);
+        }
+    }
+}
+
+// Reports the events of an upgraded connection, until it's closed
+async fn deliver_events(
+    uuid: ID,
+    mut ws_reader: WSReader,
+    ws_writer: WSWriter,
+    request: HandshakeRequest,
+    tx: &EventSender,
+    state: &ServerState,
+) {
+    let sender = ws_writer.sender();
+    let delivery = Delivery::Events(tx.clone());
+    // Once the client is closed for a full event channel, or the EventStream is dropped,
+    // its remaining messages are discarded while the closing handshake completes
+    let mut discarding = false;
+
+    // send new client event
+    if report(
+        &delivery,
+        state,
+        Event::NewClient(uuid, ws_writer, Box::new(request)),
+    )
+    .await
+    .is_err()
+    {
+        discarding = true;
+    }
+
+    while let Some(result) = ws_reader.next().await {
+        match result {
+            Ok(_) if discarding => {}
+            // send the received message event
+            Ok(message) => match report(&delivery, state, Event::NewMessage(uuid, message)).await {
+                Ok(()) => {}
+                Err(SendError::Full) => {
+                    warn!("Event channel is full, disconnecting client {}", uuid);
+                    discarding = true;
+                    let _ = sender.close(CLOSE_TRY_AGAIN_LATER, OVERLOADED_REASON).await;
+                }
+                Err(SendError::Closed) => discarding = true,
+            },
+            Err(_) if discarding => break,
+            Err(err) => {
+                let _ = report(&delivery, state, Event::Error(uuid, err)).await;
+                break;
+            }
+        }
+    }
+}
+
+// Hands an upgraded connection over to the application, in per-client mode.
+// Its messages are forwarded to the reader of the handed over connection, so the server
+// knows when it's closed, and stops tracking it
+async fn deliver_client(
+    info: ClientInfo,
+    mut ws_reader: WSReader,
+    ws_writer: WSWriter,
+    request: HandshakeRequest,
+    tx: &mpsc::Sender<ClientConnection>,
+    state: &ServerState,
+) {
+    let (read_tx, read_rx) = mpsc::channel(CLIENT_READ_BUFFER);
+    let connection = WSConnection::new(
+        ws_writer,
+        WSReader::new(ReceiverStream::new(read_rx)),
+        request,
+    );
+    if tx
+        .send(ClientConnection { info, connection })
+        .await
+        .is_err()
+    {
+        state.abandon().await;
+        return;
+    }
+
+    loop {
+        // Stops forwarding once the application drops the reader
+        let result = tokio::select! {
+            result = ws_reader.next() => match result {
+                Some(result) => result,
+                None => break,
+            },
+            _ = read_tx.closed() => break,
+        };
+        let failed = result.is_err();
+        if read_tx.send(result).await.is_err() || failed {
+            break;
+        }
+    }
+}
 
 /// A ready to use websockets server
 ///
@@ -17,87 +1008,28 @@ use tokio_rustls::{TlsAcceptor, TlsStream};
 /// It returns an EventStream, which is a stream
 /// that notifies all the relevant events of the websockets server, like new connected clients
 /// messages from a single client, disconnections and errors.
+///
+/// For binding other addresses, serving multiple listeners, or controlling the clients
+/// by their IDs through a `ServerHandle`, use `ServerBuilder`.
 pub async fn start_server_with_config(
     port: u16,
     config: Option<ServerConfig>,
 ) -> Result<EventStream, Error> {
     let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
-    let (tx, rx) = mpsc::channel(1000);
-    let web_socket_config = config.clone().unwrap_or_default().web_socket_config;
-    let tls_config = config.unwrap_or_default().tls_config;
-    // This method will return an EventStream, which holds a Receiver channel. Therefore, this
-    // spawned task will be used for processing new connections,
-    // messages, disconnections and errors, concurrently.
-    tokio::spawn(async move {
-        loop {
-            // we are using UUID, which is more flexible, and secure than incrementing IDs
-            let uuid = generate_new_uuid();
-            match listener.accept().await {
-                Ok((stream, _)) => {
-                    let socket_stream = if let Some(config) = tls_config.clone() {
-                        let acceptor = TlsAcceptor::from(config);
-                        match acceptor.accept(stream).await {
-                            Ok(tls_stream) => SocketFlowStream::Secure(TlsStream::from(tls_stream)),
-                            Err(err) => {
-                                tx.send(Event::Error(uuid, err.into())).await.unwrap();
-                                continue;
-                            }
-                        }
-                    } else {
-                        SocketFlowStream::Plain(stream)
-                    };
-
-                    let ws_connection =
-                        match accept_async_with_config(socket_stream, web_socket_config.clone())
-                            .await
-                        {
-                            Ok(conn) => conn,
-                            Err(err) => {
-                                tx.send(Event::Error(uuid, err)).await.unwrap();
-                                continue;
-                            }
-                        };
-                    // splitting the connection, so we could monitor incoming messages into a
-                    // separate task, and handover the writer to the end-user
-                    let (mut ws_reader, ws_writer) = ws_connection.split();
-
-                    // send new client event
-                    tx.send(Event::NewClient(uuid, ws_writer)).await.unwrap();
-
-                    let tx_task = tx.clone();
-                    tokio::spawn(async move {
-                        while let Some(result) = ws_reader.next().await {
-                            match result {
-                                Ok(message) => {
-                                    tx_task
-                                        .send(Event::NewMessage(uuid, message))
-                                        .await
-                                        .unwrap();
-                                    // send the received message event
-                                }
-                                Err(err) => {
-                                    tx_task.send(Event::Error(uuid, err)).await.unwrap();
-                                    break;
-                                }
-                            }
-                        }
-
-                        // send disconnect event when connection closed
-                        let _ = tx_task.send(Event::Disconnect(uuid)).await;
-                    });
-                }
-                Err(error) => {
-                    tx.send(Event::Error(uuid, error.into())).await.unwrap();
-                    continue;
-                }
-            }
-        }
-    });
+    let config = config.unwrap_or_default();
+    let (tx, rx) = server_event_queue(&config);
+    let tls_acceptor = ServerTlsAcceptor::from_config(&config);
+    spawn_accept_loop(
+        listener,
+        tls_acceptor,
+        Delivery::Events(tx),
+        Arc::new(ServerState::new(&config)),
+    );
 
     // Delivery the EventStream to the end-user, without blocking this function call
     // by the spawned task.
     // Thus, processing and sending new events concurrently
-    Ok(EventStream::new(rx))
+    Ok(EventStream::from_queue(rx))
 }
 
 /// A ready to use websockets server
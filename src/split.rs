@@ -2,7 +2,8 @@ use crate::config::WebSocketConfig;
 use crate::encoder::Encoder;
 use crate::error::Error;
 use crate::frame::{Frame, OpCode};
-use crate::message::Message;
+use crate::message::{Message, PreparedMessage};
+use crate::rate_limit::TokenBucket;
 use crate::write::Writer;
 use bytes::BytesMut;
 use futures::Stream;
@@ -38,6 +39,8 @@ pub struct WSWriter {
     pub writer: Arc<Mutex<Writer>>,
     pub web_socket_config: WebSocketConfig,
     encoder: Encoder,
+    // Shared with the senders, so they all draw from the same outbound budget
+    outbound_limiter: Option<Arc<std::sync::Mutex<TokenBucket>>>,
 }
 
 impl WSWriter {
@@ -46,10 +49,14 @@ impl WSWriter {
         web_socket_config: WebSocketConfig,
         encoder: Encoder,
     ) -> Self {
+        let outbound_limiter = web_socket_config
+            .max_outbound_bytes_per_second
+            .map(|rate| Arc::new(std::sync::Mutex::new(TokenBucket::new(rate))));
         Self {
             writer,
             web_socket_config,
             encoder,
+            outbound_limiter,
         }
     }
 
@@ -123,6 +130,7 @@ impl WSWriter {
 
         let chunks = data.chunks(fragment_size);
         let total_chunks = chunks.len();
+        let mut frames = Vec::with_capacity(total_chunks);
 
         for (i, chunk) in chunks.enumerate() {
             let is_final = i == total_chunks - 1;
@@ -132,16 +140,12 @@ impl WSWriter {
                 OpCode::Continue
             };
 
-            self.write_frames(vec![Frame::new(
-                is_final,
-                opcode,
-                Vec::from(chunk),
-                compressed,
-            )])
-            .await?
+            frames.push(Frame::new(is_final, opcode, Vec::from(chunk), compressed));
         }
 
-        Ok(())
+        // All fragments are written at once, so messages sent through a WSSender
+        // can't be interleaved with them
+        self.write_frames(frames).await
     }
 
     pub(crate) fn check_compression(&mut self, data: &mut Vec<u8>) -> Result<bool, Error> {
@@ -183,29 +187,15 @@ impl WSWriter {
             }]);
         }
 
-        let max_frame_size = self.web_socket_config.max_frame_size.unwrap_or_default();
-        let mut frames = Vec::new();
         // This function will check if compression is enabled, and apply if needed
         let compressed = self.check_compression(&mut payload)?;
 
-        for chunk in payload.chunks(max_frame_size) {
-            frames.push(Frame {
-                final_fragment: false,
-                opcode: if frames.is_empty() {
-                    opcode.clone()
-                } else {
-                    OpCode::Continue
-                },
-                payload: chunk.to_vec(),
-                compressed,
-            });
-        }
-
-        if let Some(last_frame) = frames.last_mut() {
-            last_frame.final_fragment = true;
-        }
-
-        Ok(frames)
+        Ok(split_into_frames(
+            opcode,
+            payload,
+            self.web_socket_config.max_frame_size.unwrap_or_default(),
+            compressed,
+        ))
     }
 
     pub(crate) async fn write_message(&mut self, message: Message) -> Result<(), Error> {
@@ -218,21 +208,159 @@ impl WSWriter {
     }
 
     pub(crate) async fn write_frames(&mut self, frames: Vec<Frame>) -> Result<(), Error> {
-        // For compressed messages, regardless if it's fragmented or not, we always set the RSV1 bit
-        // for the first frame.
-        let mut set_rsv1_first_frame = !frames.is_empty() && frames[0].compressed;
-
-        for frame in frames {
-            self.writer
-                .lock()
-                .await
-                .write_frame(frame, set_rsv1_first_frame)
-                .await?;
-            // Setting it to false,
-            // since we only need
-            // to set RSV1 bit for the first frame if compression is enabled
-            set_rsv1_first_frame = false;
+        let length = frames.iter().map(|frame| frame.payload.len()).sum();
+        throttle(self.outbound_limiter.as_ref(), length).await;
+
+        self.writer.lock().await.write_frames(frames).await
+    }
+
+    /// Sends a message that was encoded once, for many connections
+    pub async fn send_prepared(&mut self, prepared: &PreparedMessage) -> Result<(), Error> {
+        if prepared.len() > self.web_socket_config.max_message_size.unwrap_or_default() {
+            return Err(Error::MaxMessageSize);
         }
-        Ok(())
+
+        throttle(self.outbound_limiter.as_ref(), prepared.len()).await;
+
+        self.writer
+            .lock()
+            .await
+            .write_prepared(
+                prepared,
+                shares_compression(&self.web_socket_config),
+                self.web_socket_config.max_frame_size.unwrap_or_default(),
+            )
+            .await
+    }
+
+    /// Returns a `WSSender` for this connection, which can be cloned and moved into other tasks
+    pub fn sender(&self) -> WSSender {
+        WSSender {
+            writer: self.writer.clone(),
+            outbound_limiter: self.outbound_limiter.clone(),
+            max_frame_size: self.web_socket_config.max_frame_size.unwrap_or_default(),
+            max_message_size: self.web_socket_config.max_message_size.unwrap_or_default(),
+            shares_compression: shares_compression(&self.web_socket_config),
+        }
+    }
+}
+
+/// A cloneable handle for sending messages over a connection, obtained from `WSWriter::sender`.
+///
+/// It shares the socket with its `WSWriter`, so it can be handed over to other tasks,
+/// like the ones broadcasting to many connections.
+/// Messages sent through it aren't compressed, which permessage-deflate always allows,
+/// so the compression context of the `WSWriter` is left untouched.
+/// Messages sent through it count against the same `max_outbound_bytes_per_second` budget
+/// as the ones sent through the `WSWriter`.
+#[derive(Clone)]
+pub struct WSSender {
+    writer: Arc<Mutex<Writer>>,
+    outbound_limiter: Option<Arc<std::sync::Mutex<TokenBucket>>>,
+    max_frame_size: usize,
+    max_message_size: usize,
+    shares_compression: bool,
+}
+
+impl WSSender {
+    pub async fn send_message(&self, message: Message) -> Result<(), Error> {
+        if message.as_binary().len() > self.max_message_size {
+            return Err(Error::MaxMessageSize);
+        }
+
+        let (opcode, payload) = match message {
+            Message::Text(text) => (OpCode::Text, text.into_bytes()),
+            Message::Binary(data) => (OpCode::Binary, data),
+        };
+        throttle(self.outbound_limiter.as_ref(), payload.len()).await;
+        let frames = split_into_frames(opcode, payload, self.max_frame_size, false);
+        self.writer.lock().await.write_frames(frames).await
     }
+
+    /// Sends a message that was encoded once, for many connections.
+    /// Unlike the other messages sent through a WSSender, it may be compressed,
+    /// since it doesn't depend on the compression context of the connection
+    pub async fn send_prepared(&self, prepared: &PreparedMessage) -> Result<(), Error> {
+        if prepared.len() > self.max_message_size {
+            return Err(Error::MaxMessageSize);
+        }
+
+        throttle(self.outbound_limiter.as_ref(), prepared.len()).await;
+
+        self.writer
+            .lock()
+            .await
+            .write_prepared(prepared, self.shares_compression, self.max_frame_size)
+            .await
+    }
+
+    /// Starts the closing handshake, sending a Close frame with the given status code and reason,
+    /// unless it was already started
+    pub async fn close(&self, code: u16, reason: &str) -> Result<(), Error> {
+        let mut writer = self.writer.lock().await;
+        if writer.close_sent() {
+            return Ok(());
+        }
+        writer.write_frame(Frame::close(code, reason), false).await
+    }
+
+    // Drops the connection without waiting for the closing handshake
+    pub(crate) async fn force_close(&self) {
+        self.writer.lock().await.force_close();
+    }
+}
+
+// Waits before writing the given amount of payload bytes, when the outbound bandwidth cap
+// is reached. The bucket is only locked for taking the tokens, not while waiting
+async fn throttle(limiter: Option<&Arc<std::sync::Mutex<TokenBucket>>>, length: usize) {
+    let delay = limiter.and_then(|limiter| limiter.lock().unwrap().take(length));
+    if let Some(delay) = delay {
+        sleep(delay).await;
+    }
+}
+
+// Whether a connection can be sent the shared compressed form of prepared messages,
+// which requires that every message is compressed on its own, with the default window
+fn shares_compression(config: &WebSocketConfig) -> bool {
+    config.extensions.as_ref().is_some_and(|extensions| {
+        extensions.permessage_deflate
+            && extensions.server_no_context_takeover == Some(true)
+            && extensions
+                .server_max_window_bits
+                .is_none_or(|window_bits| window_bits == 15)
+    })
+}
+
+// Splits a message payload into frames of up to max_frame_size, where the first one carries
+// the message opcode, and the next ones are continuation frames
+pub(crate) fn split_into_frames(
+    opcode: OpCode,
+    payload: Vec<u8>,
+    max_frame_size: usize,
+    compressed: bool,
+) -> Vec<Frame> {
+    // Empty payloads still need a single frame
+    if payload.is_empty() {
+        return vec![Frame::new(true, opcode, payload, false)];
+    }
+
+    let mut frames = Vec::new();
+    for chunk in payload.chunks(max_frame_size) {
+        frames.push(Frame {
+            final_fragment: false,
+            opcode: if frames.is_empty() {
+                opcode.clone()
+            } else {
+                OpCode::Continue
+            },
+            payload: chunk.to_vec(),
+            compressed,
+        });
+    }
+
+    if let Some(last_frame) = frames.last_mut() {
+        last_frame.final_fragment = true;
+    }
+
+    frames
 }
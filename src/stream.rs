@@ -1,7 +1,14 @@
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+use crate::tls::TlsInfo;
+use std::io;
+use std::net::SocketAddr;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::TcpStream;
+#[cfg(feature = "native-tls")]
+use tokio_native_tls::TlsStream as NativeTlsStream;
+#[cfg(feature = "rustls-tls")]
 use tokio_rustls::TlsStream as RustTlsStream;
 
 // We need to implement AsyncRead and AsyncWrite for SocketFlowStream,
@@ -10,9 +17,57 @@ use tokio_rustls::TlsStream as RustTlsStream;
 // This is a good solution, when you don't want to use a generic for your own functions
 // If we use a generic in accept_async(handshake.rs), we will need to add trait signatures to all the
 // functions that are called inside accept_async recursively.
+// The TLS variants are much larger than a TcpStream, but boxing them would change the public
+// variants, that users build themselves
+#[allow(clippy::large_enum_variant)]
 pub enum SocketFlowStream {
     Plain(TcpStream),
+    #[cfg(feature = "rustls-tls")]
     Secure(RustTlsStream<TcpStream>),
+    #[cfg(feature = "native-tls")]
+    NativeTls(NativeTlsStream<TcpStream>),
+}
+
+impl SocketFlowStream {
+    /// Returns the remote address of the underlying TCP socket
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp_stream().peer_addr()
+    }
+
+    /// Returns the local address of the underlying TCP socket
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp_stream().local_addr()
+    }
+
+    /// Returns the details of the TLS session, or `None` for plain connections
+    #[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        match self {
+            SocketFlowStream::Plain(_) => None,
+            #[cfg(feature = "rustls-tls")]
+            SocketFlowStream::Secure(RustTlsStream::Server(s)) => {
+                let (_, connection) = s.get_ref();
+                let server_name = connection.server_name().map(String::from);
+                Some(TlsInfo::from_common_state(connection, server_name))
+            }
+            #[cfg(feature = "rustls-tls")]
+            SocketFlowStream::Secure(RustTlsStream::Client(s)) => {
+                Some(TlsInfo::from_common_state(s.get_ref().1, None))
+            }
+            #[cfg(feature = "native-tls")]
+            SocketFlowStream::NativeTls(s) => Some(TlsInfo::from_native_tls(s.get_ref())),
+        }
+    }
+
+    fn tcp_stream(&self) -> &TcpStream {
+        match self {
+            SocketFlowStream::Plain(s) => s,
+            #[cfg(feature = "rustls-tls")]
+            SocketFlowStream::Secure(s) => s.get_ref().0,
+            #[cfg(feature = "native-tls")]
+            SocketFlowStream::NativeTls(s) => s.get_ref().get_ref().get_ref(),
+        }
+    }
 }
 
 impl AsyncRead for SocketFlowStream {
@@ -23,7 +78,10 @@ impl AsyncRead for SocketFlowStream {
     ) -> Poll<std::io::Result<()>> {
         match self.get_mut() {
             SocketFlowStream::Plain(ref mut s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(feature = "rustls-tls")]
             SocketFlowStream::Secure(s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(feature = "native-tls")]
+            SocketFlowStream::NativeTls(s) => Pin::new(s).poll_read(cx, buf),
         }
     }
 }
@@ -36,14 +94,20 @@ impl AsyncWrite for SocketFlowStream {
     ) -> Poll<Result<usize, std::io::Error>> {
         match self.get_mut() {
             SocketFlowStream::Plain(ref mut s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(feature = "rustls-tls")]
             SocketFlowStream::Secure(s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(feature = "native-tls")]
+            SocketFlowStream::NativeTls(s) => Pin::new(s).poll_write(cx, buf),
         }
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
         match self.get_mut() {
             SocketFlowStream::Plain(ref mut s) => Pin::new(s).poll_flush(cx),
+            #[cfg(feature = "rustls-tls")]
             SocketFlowStream::Secure(s) => Pin::new(s).poll_flush(cx),
+            #[cfg(feature = "native-tls")]
+            SocketFlowStream::NativeTls(s) => Pin::new(s).poll_flush(cx),
         }
     }
 
@@ -53,7 +117,10 @@ impl AsyncWrite for SocketFlowStream {
     ) -> Poll<Result<(), std::io::Error>> {
         match self.get_mut() {
             SocketFlowStream::Plain(ref mut s) => Pin::new(s).poll_shutdown(cx),
+            #[cfg(feature = "rustls-tls")]
             SocketFlowStream::Secure(s) => Pin::new(s).poll_shutdown(cx),
+            #[cfg(feature = "native-tls")]
+            SocketFlowStream::NativeTls(s) => Pin::new(s).poll_shutdown(cx),
         }
     }
 }
@@ -0,0 +1,264 @@
+use pki_types::CertificateDer;
+#[cfg(feature = "rustls-tls")]
+use rustls::{CipherSuite, CommonState, ProtocolVersion};
+#[cfg(feature = "native-tls")]
+use std::io::{Read, Write};
+use std::net::IpAddr;
+use x509_parser::der_parser::asn1_rs::{Oid, Tag};
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+use x509_parser::x509::AttributeTypeAndValue;
+
+/// A name from the Subject Alternative Name extension of a certificate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubjectAltName {
+    Dns(String),
+    Ip(IpAddr),
+    Email(String),
+    Uri(String),
+}
+
+/// Details about the TLS session of a connection, available for both servers and clients.
+///
+/// For servers configured with client certificate verification, like rustls
+/// `WebPkiClientVerifier`, the peer certificates are the ones presented by the client,
+/// which can be used for authorizing the client by its certificate identity.
+/// For clients, the peer certificates are the ones presented by the server.
+///
+/// The native-tls backend only exposes the peer end-entity certificate and the ALPN protocol.
+#[derive(Debug, Clone)]
+pub struct TlsInfo {
+    peer_certificates: Vec<CertificateDer<'static>>,
+    alpn_protocol: Option<Vec<u8>>,
+    #[cfg(feature = "rustls-tls")]
+    cipher_suite: Option<CipherSuite>,
+    #[cfg(feature = "rustls-tls")]
+    protocol_version: Option<ProtocolVersion>,
+    server_name: Option<String>,
+}
+
+impl TlsInfo {
+    #[cfg(feature = "rustls-tls")]
+    pub(crate) fn from_common_state(state: &CommonState, server_name: Option<String>) -> Self {
+        Self {
+            peer_certificates: state
+                .peer_certificates()
+                .map(|certs| certs.iter().map(|cert| cert.clone().into_owned()).collect())
+                .unwrap_or_default(),
+            alpn_protocol: state.alpn_protocol().map(<[u8]>::to_vec),
+            cipher_suite: state.negotiated_cipher_suite().map(|suite| suite.suite()),
+            protocol_version: state.protocol_version(),
+            server_name,
+        }
+    }
+
+    #[cfg(feature = "native-tls")]
+    pub(crate) fn from_native_tls<S: Read + Write>(stream: &native_tls::TlsStream<S>) -> Self {
+        Self {
+            peer_certificates: stream
+                .peer_certificate()
+                .ok()
+                .flatten()
+                .and_then(|cert| cert.to_der().ok())
+                .map(|der| vec![CertificateDer::from(der)])
+                .unwrap_or_default(),
+            alpn_protocol: stream.negotiated_alpn().ok().flatten(),
+            #[cfg(feature = "rustls-tls")]
+            cipher_suite: None,
+            #[cfg(feature = "rustls-tls")]
+            protocol_version: None,
+            server_name: None,
+        }
+    }
+
+    #[cfg(all(test, feature = "rustls-tls"))]
+    pub(crate) fn from_peer_certificates(peer_certificates: Vec<CertificateDer<'static>>) -> Self {
+        Self {
+            peer_certificates,
+            alpn_protocol: None,
+            #[cfg(feature = "rustls-tls")]
+            cipher_suite: None,
+            #[cfg(feature = "rustls-tls")]
+            protocol_version: None,
+            server_name: None,
+        }
+    }
+
+    /// The certificate chain presented by the peer, starting with the end-entity certificate.
+    /// It's empty when the peer didn't present any certificate
+    pub fn peer_certificates(&self) -> &[CertificateDer<'static>] {
+        &self.peer_certificates
+    }
+
+    /// The distinguished name of the peer end-entity certificate subject,
+    /// formatted as in RFC 4514, like `CN=billing-service,O=Socket Flow,C=BR`.
+    /// Special characters of the attribute values are escaped, so different names never
+    /// produce the same string.
+    /// It's `None` when an attribute value isn't a valid string
+    pub fn subject(&self) -> Option<String> {
+        let certificate = parse_certificate(self.peer_certificates.first()?)?;
+        let relative_names = certificate
+            .subject()
+            .iter_rdn()
+            .map(|relative_name| {
+                relative_name
+                    .iter()
+                    .map(|attribute| {
+                        let value = decode_value(attribute)?;
+                        Some(format!(
+                            "{}={}",
+                            attribute_name(attribute.attr_type()),
+                            escape_value(&value)
+                        ))
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .map(|attributes| attributes.join("+"))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        // RFC 4514 starts with the last, and most specific, relative name
+        Some(
+            relative_names
+                .into_iter()
+                .rev()
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+    }
+
+    /// The common name (CN) of the peer end-entity certificate subject, unescaped.
+    /// It's `None` when the common name isn't a valid string
+    pub fn common_name(&self) -> Option<String> {
+        let certificate = parse_certificate(self.peer_certificates.first()?)?;
+        let common_name = certificate.subject().iter_common_name().last()?;
+        decode_value(common_name)
+    }
+
+    /// The Subject Alternative Names of the peer end-entity certificate.
+    /// Names that aren't valid strings are skipped
+    pub fn subject_alt_names(&self) -> Vec<SubjectAltName> {
+        let Some(certificate) = self.peer_certificates.first().and_then(parse_certificate) else {
+            return Vec::new();
+        };
+        let Ok(Some(extension)) = certificate.subject_alternative_name() else {
+            return Vec::new();
+        };
+
+        extension
+            .value
+            .general_names
+            .iter()
+            .filter_map(|name| match name {
+                GeneralName::DNSName(name) => Some(SubjectAltName::Dns(name.to_string())),
+                GeneralName::RFC822Name(email) => Some(SubjectAltName::Email(email.to_string())),
+                GeneralName::URI(uri) => Some(SubjectAltName::Uri(uri.to_string())),
+                GeneralName::IPAddress(address) => match address.len() {
+                    4 => <[u8; 4]>::try_from(*address).ok().map(IpAddr::from),
+                    16 => <[u8; 16]>::try_from(*address).ok().map(IpAddr::from),
+                    _ => None,
+                }
+                .map(SubjectAltName::Ip),
+                // Other kinds of names, like directory names, aren't exposed
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The protocol negotiated with ALPN, if any
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_deref()
+    }
+
+    /// The negotiated cipher suite
+    #[cfg(feature = "rustls-tls")]
+    pub fn cipher_suite(&self) -> Option<CipherSuite> {
+        self.cipher_suite
+    }
+
+    /// The negotiated TLS version
+    #[cfg(feature = "rustls-tls")]
+    pub fn protocol_version(&self) -> Option<ProtocolVersion> {
+        self.protocol_version
+    }
+
+    /// For servers, the hostname requested by the client through SNI,
+    /// which can be used for routing virtual hosts to different handlers
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+}
+
+fn parse_certificate<'a>(certificate: &'a CertificateDer<'_>) -> Option<X509Certificate<'a>> {
+    X509Certificate::from_der(certificate.as_ref())
+        .ok()
+        .map(|(_, certificate)| certificate)
+}
+
+// Short names of the attributes, as registered for LDAP (RFC 4514 and RFC 4519),
+// otherwise the dotted OID
+fn attribute_name(oid: &Oid<'_>) -> String {
+    let oid = oid.to_id_string();
+    let name = match oid.as_str() {
+        "2.5.4.3" => "CN",
+        "2.5.4.5" => "serialNumber",
+        "2.5.4.6" => "C",
+        "2.5.4.7" => "L",
+        "2.5.4.8" => "ST",
+        "2.5.4.9" => "STREET",
+        "2.5.4.10" => "O",
+        "2.5.4.11" => "OU",
+        "0.9.2342.19200300.100.1.1" => "UID",
+        "0.9.2342.19200300.100.1.25" => "DC",
+        _ => return oid,
+    };
+    String::from(name)
+}
+
+// Decodes the value of an attribute, failing on values that aren't strings, or that aren't
+// valid in their string type, instead of replacing the invalid characters, since different
+// values would end up with the same text
+fn decode_value(attribute: &AttributeTypeAndValue<'_>) -> Option<String> {
+    let value = attribute.attr_value();
+    let data = value.as_bytes();
+    match value.tag() {
+        Tag::Utf8String | Tag::PrintableString | Tag::Ia5String | Tag::NumericString => {
+            attribute.as_str().ok().map(String::from)
+        }
+        // Legacy string types, which are only accepted with ASCII content
+        Tag::TeletexString | Tag::VisibleString if data.is_ascii() => {
+            String::from_utf8(data.to_vec()).ok()
+        }
+        Tag::BmpString if data.len() % 2 == 0 => {
+            let utf16 = data
+                .chunks_exact(2)
+                .map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]))
+                .collect::<Vec<_>>();
+            String::from_utf16(&utf16).ok()
+        }
+        Tag::UniversalString if data.len() % 4 == 0 => data
+            .chunks_exact(4)
+            .map(|chunk| {
+                char::from_u32(u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+// Escapes an attribute value, as in RFC 4514 section 2.4
+fn escape_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for (index, character) in value.char_indices() {
+        match character {
+            '"' | '+' | ',' | ';' | '<' | '>' | '\\' => {
+                escaped.push('\\');
+                escaped.push(character);
+            }
+            '#' if index == 0 => escaped.push_str("\\#"),
+            ' ' if index == 0 || index + 1 == value.len() => escaped.push_str("\\ "),
+            '\0' => escaped.push_str("\\00"),
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
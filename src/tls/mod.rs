@@ -0,0 +1,125 @@
+//! TLS support, which is provided by rustls, through the `aws-lc-rs` (default) or `ring` features,
+//! or by the TLS library of the platform, through the `native-tls` feature.
+//!
+//! Without any of these features, TLS is left out entirely, and connecting to `wss` URLs
+//! returns `Error::TlsNotEnabled`.
+
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+mod info;
+#[cfg(feature = "native-tls")]
+mod native_backend;
+#[cfg(feature = "rustls-tls")]
+mod rustls_backend;
+
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+pub use info::{SubjectAltName, TlsInfo};
+#[cfg(feature = "native-tls")]
+pub use native_backend::NativeTlsAcceptor;
+#[cfg(all(test, feature = "rustls-tls"))]
+pub(crate) use rustls_backend::{
+    client_config_builder, client_tls_config, crypto_provider, decode_spki_pin,
+    server_config_builder,
+};
+#[cfg(feature = "rustls-tls")]
+pub use rustls_backend::{
+    client_config_from_ca_file, client_config_from_ca_pem, load_certs, load_key, parse_certs,
+    parse_key, server_config_from_pem, server_config_from_pem_files, ReloadableCertResolver,
+    SniCertResolver,
+};
+
+use crate::config::{ClientConfig, ServerConfig};
+use crate::error::Error;
+use crate::stream::SocketFlowStream;
+use tokio::net::TcpStream;
+
+#[cfg(all(
+    feature = "rustls-tls",
+    not(any(feature = "aws-lc-rs", feature = "ring"))
+))]
+compile_error!(
+    "the rustls backend requires a crypto provider, enable either `aws-lc-rs` or `ring`"
+);
+
+// Whether this build supports wss connections
+pub(crate) const TLS_ENABLED: bool = cfg!(any(feature = "rustls-tls", feature = "native-tls"));
+
+// Performs the client TLS handshake over a connected TCP stream.
+// rustls is used by default, and native-tls when a custom native-tls connector was set,
+// or when it's the only backend enabled
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+pub(crate) async fn connect_tls(
+    stream: TcpStream,
+    host: String,
+    config: &ClientConfig,
+) -> Result<SocketFlowStream, Error> {
+    // The server name is used for SNI, and for validating the server certificate
+    let server_name = config.server_name.clone().unwrap_or(host);
+
+    #[cfg(all(feature = "rustls-tls", feature = "native-tls"))]
+    if config.native_tls_connector.is_some() {
+        return native_backend::connect(stream, &server_name, config).await;
+    }
+
+    #[cfg(feature = "rustls-tls")]
+    return rustls_backend::connect(stream, server_name, config).await;
+
+    #[cfg(not(feature = "rustls-tls"))]
+    return native_backend::connect(stream, &server_name, config).await;
+}
+
+#[cfg(not(any(feature = "rustls-tls", feature = "native-tls")))]
+pub(crate) async fn connect_tls(
+    _stream: TcpStream,
+    _host: String,
+    _config: &ClientConfig,
+) -> Result<SocketFlowStream, Error> {
+    Err(Error::TlsNotEnabled)
+}
+
+// The TLS acceptor of a server, built once from ServerConfig, and used for every connection
+#[derive(Clone)]
+pub(crate) enum ServerTlsAcceptor {
+    #[cfg(feature = "rustls-tls")]
+    Rustls(tokio_rustls::TlsAcceptor),
+    #[cfg(feature = "native-tls")]
+    Native(tokio_native_tls::TlsAcceptor),
+}
+
+impl ServerTlsAcceptor {
+    // Returns None when TLS isn't configured, so the server accepts plain connections.
+    // Since certificates are picked by the rustls config on every handshake,
+    // a certificate resolver like ReloadableCertResolver can rotate them while the server is running
+    #[allow(unused_variables)]
+    pub(crate) fn from_config(config: &ServerConfig) -> Option<Self> {
+        #[cfg(feature = "rustls-tls")]
+        if let Some(tls_config) = &config.tls_config {
+            return Some(Self::Rustls(tokio_rustls::TlsAcceptor::from(
+                tls_config.clone(),
+            )));
+        }
+
+        #[cfg(feature = "native-tls")]
+        if let Some(acceptor) = &config.native_tls_acceptor {
+            return Some(Self::Native(acceptor.inner().clone()));
+        }
+
+        None
+    }
+
+    #[cfg_attr(
+        not(any(feature = "rustls-tls", feature = "native-tls")),
+        allow(unused_variables)
+    )]
+    pub(crate) async fn accept(&self, stream: TcpStream) -> Result<SocketFlowStream, Error> {
+        match *self {
+            #[cfg(feature = "rustls-tls")]
+            Self::Rustls(ref acceptor) => Ok(SocketFlowStream::Secure(
+                tokio_rustls::TlsStream::from(acceptor.accept(stream).await?),
+            )),
+            #[cfg(feature = "native-tls")]
+            Self::Native(ref acceptor) => {
+                Ok(SocketFlowStream::NativeTls(acceptor.accept(stream).await?))
+            }
+        }
+    }
+}
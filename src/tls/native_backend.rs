@@ -0,0 +1,96 @@
+use crate::config::ClientConfig;
+use crate::error::Error;
+use crate::stream::SocketFlowStream;
+use native_tls::{Certificate, Identity, TlsConnector};
+use std::fmt;
+use std::fs;
+use tokio::net::TcpStream;
+
+pub(crate) async fn connect(
+    stream: TcpStream,
+    server_name: &str,
+    config: &ClientConfig,
+) -> Result<SocketFlowStream, Error> {
+    let connector = tokio_native_tls::TlsConnector::from(native_tls_connector(config)?);
+    let tls_stream = connector.connect(server_name, stream).await?;
+    Ok(SocketFlowStream::NativeTls(tls_stream))
+}
+
+// Returns the custom connector set by the end-user, or builds one from the TLS options of
+// ClientConfig. Unlike rustls, the system trusted CA certificates are used by default
+fn native_tls_connector(config: &ClientConfig) -> Result<TlsConnector, Error> {
+    if let Some(connector) = &config.native_tls_connector {
+        return Ok(connector.clone());
+    }
+
+    if !config.pinned_spki_sha256.is_empty() {
+        return Err(Error::TlsConfigError(String::from(
+            "certificate pinning is only supported by the rustls backend",
+        )));
+    }
+
+    let mut builder = TlsConnector::builder();
+    // Like the rustls backend, the CA file replaces the trusted CA certificates
+    if let Some(ca_file) = &config.ca_file {
+        for cert in Certificate::stack_from_pem(&fs::read(ca_file)?)? {
+            builder.add_root_certificate(cert);
+        }
+        builder.disable_built_in_roots(true);
+    }
+
+    match (&config.client_cert_file, &config.client_key_file) {
+        (Some(cert_file), Some(key_file)) => {
+            builder.identity(Identity::from_pkcs8(
+                &fs::read(cert_file)?,
+                &fs::read(key_file)?,
+            )?);
+        }
+        (None, None) => {}
+        _ => {
+            return Err(Error::TlsConfigError(String::from(
+                "client_cert_file and client_key_file must be set together",
+            )))
+        }
+    }
+
+    let alpn_protocols = config
+        .alpn_protocols
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    if !alpn_protocols.is_empty() {
+        builder.request_alpns(&alpn_protocols);
+    }
+
+    Ok(builder.build()?)
+}
+
+/// A native-tls acceptor, for enabling TLS on servers through the TLS library of the platform,
+/// like OpenSSL
+#[derive(Clone)]
+pub struct NativeTlsAcceptor(tokio_native_tls::TlsAcceptor);
+
+impl NativeTlsAcceptor {
+    /// Builds an acceptor from the PEM files of the certificate chain, and its PKCS#8 private key
+    pub fn from_pem_files(cert_file: &str, key_file: &str) -> Result<Self, Error> {
+        let identity = Identity::from_pkcs8(&fs::read(cert_file)?, &fs::read(key_file)?)?;
+        Ok(Self::from(native_tls::TlsAcceptor::new(identity)?))
+    }
+
+    pub(crate) fn inner(&self) -> &tokio_native_tls::TlsAcceptor {
+        &self.0
+    }
+}
+
+impl From<native_tls::TlsAcceptor> for NativeTlsAcceptor {
+    fn from(acceptor: native_tls::TlsAcceptor) -> Self {
+        Self(tokio_native_tls::TlsAcceptor::from(acceptor))
+    }
+}
+
+// native-tls acceptors don't implement Debug, which is required by ServerConfig
+impl fmt::Debug for NativeTlsAcceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeTlsAcceptor").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,652 @@
+use crate::config::ClientConfig;
+use crate::error::Error;
+use crate::stream::SocketFlowStream;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use log::warn;
+use pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::hash::{Hash, HashAlgorithm};
+use rustls::crypto::CryptoProvider;
+use rustls::server::{ClientHello, ParsedCertificate, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{ConfigBuilder, DigitallySignedStruct, RootCertStore, SignatureScheme, WantsVerifier};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::SystemTime;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration};
+use tokio_rustls::{TlsConnector, TlsStream};
+
+const SPKI_PIN_PREFIX: &str = "sha256/";
+// Used in errors, in place of the file path, when the PEM content is parsed from memory
+const IN_MEMORY_PEM: &str = "in-memory PEM";
+
+// The crypto provider of the rustls configs built by this library, which is the process default,
+// when the application installed one, otherwise the provider picked by the cargo features.
+// aws-lc-rs is preferred when both `aws-lc-rs` and `ring` are enabled
+pub(crate) fn crypto_provider() -> Arc<CryptoProvider> {
+    if let Some(provider) = CryptoProvider::get_default() {
+        return provider.clone();
+    }
+
+    Arc::new(feature_provider())
+}
+
+#[cfg(feature = "aws-lc-rs")]
+fn feature_provider() -> CryptoProvider {
+    rustls::crypto::aws_lc_rs::default_provider()
+}
+
+#[cfg(all(feature = "ring", not(feature = "aws-lc-rs")))]
+fn feature_provider() -> CryptoProvider {
+    rustls::crypto::ring::default_provider()
+}
+
+// Builds without a provider are stopped by a compile_error, this only avoids further errors
+#[cfg(not(any(feature = "aws-lc-rs", feature = "ring")))]
+fn feature_provider() -> CryptoProvider {
+    unreachable!("no crypto provider was enabled")
+}
+
+pub(crate) fn client_config_builder(
+) -> Result<ConfigBuilder<rustls::ClientConfig, WantsVerifier>, Error> {
+    Ok(
+        rustls::ClientConfig::builder_with_provider(crypto_provider())
+            .with_safe_default_protocol_versions()?,
+    )
+}
+
+pub(crate) fn server_config_builder(
+) -> Result<ConfigBuilder<rustls::ServerConfig, WantsVerifier>, Error> {
+    Ok(
+        rustls::ServerConfig::builder_with_provider(crypto_provider())
+            .with_safe_default_protocol_versions()?,
+    )
+}
+
+// The TLS related options of ClientConfig, which are used as the key for caching the
+// rustls configs built from them
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ClientTlsOptions {
+    ca_file: Option<String>,
+    client_cert_file: Option<String>,
+    client_key_file: Option<String>,
+    native_roots: bool,
+    pinned_spki_sha256: Vec<String>,
+    alpn_protocols: Vec<String>,
+}
+
+impl From<&ClientConfig> for ClientTlsOptions {
+    fn from(config: &ClientConfig) -> Self {
+        Self {
+            ca_file: config.ca_file.clone(),
+            client_cert_file: config.client_cert_file.clone(),
+            client_key_file: config.client_key_file.clone(),
+            native_roots: config.native_roots,
+            pinned_spki_sha256: config.pinned_spki_sha256.clone(),
+            alpn_protocols: config.alpn_protocols.clone(),
+        }
+    }
+}
+
+// rustls configs are cached by their options, so every connection using the same options shares
+// the same rustls config, and consequently the same session cache.
+// Thus, reconnections resume the previous TLS session, instead of doing a full handshake.
+// The modification time and size of the files are kept along with the config, which is rebuilt
+// once any of them changes, like when certificates are rotated
+static CLIENT_TLS_CONFIGS: OnceLock<Mutex<HashMap<ClientTlsOptions, CachedClientTlsConfig>>> =
+    OnceLock::new();
+// Different options are only expected from a handful of configs, so the cache is bounded
+// for not growing forever, when options are created dynamically
+const MAX_CACHED_CLIENT_TLS_CONFIGS: usize = 64;
+
+struct CachedClientTlsConfig {
+    file_stamps: Vec<Option<(SystemTime, u64)>>,
+    tls_config: Arc<rustls::ClientConfig>,
+}
+
+impl ClientTlsOptions {
+    // The modification time and size of every file of the options
+    fn file_stamps(&self) -> Vec<Option<(SystemTime, u64)>> {
+        [&self.ca_file, &self.client_cert_file, &self.client_key_file]
+            .into_iter()
+            .flatten()
+            .map(|file| {
+                let metadata = fs::metadata(file).ok()?;
+                Some((metadata.modified().ok()?, metadata.len()))
+            })
+            .collect()
+    }
+}
+
+// Returns the rustls config that should be used by a client, which is either the custom one
+// set by the end-user, or a config built from the TLS options of ClientConfig
+pub(crate) fn client_tls_config(config: &ClientConfig) -> Result<Arc<rustls::ClientConfig>, Error> {
+    if let Some(tls_config) = &config.tls_config {
+        return Ok(tls_config.clone());
+    }
+
+    let options = ClientTlsOptions::from(config);
+    let file_stamps = options.file_stamps();
+    let mut cache = CLIENT_TLS_CONFIGS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    match cache.get(&options) {
+        Some(cached) if cached.file_stamps == file_stamps => {
+            return Ok(cached.tls_config.clone());
+        }
+        Some(_) => {}
+        None if cache.len() >= MAX_CACHED_CLIENT_TLS_CONFIGS => {
+            if let Some(evicted) = cache.keys().next().cloned() {
+                cache.remove(&evicted);
+            }
+        }
+        None => {}
+    }
+
+    let tls_config = Arc::new(build_client_tls_config(&options)?);
+    cache.insert(
+        options,
+        CachedClientTlsConfig {
+            file_stamps,
+            tls_config: tls_config.clone(),
+        },
+    );
+    Ok(tls_config)
+}
+
+pub(crate) async fn connect(
+    stream: TcpStream,
+    server_name: String,
+    config: &ClientConfig,
+) -> Result<SocketFlowStream, Error> {
+    // The rustls config is either the one provided by the end-user,
+    // or built from the TLS options of ClientConfig, and shared between connections
+    // with the same options, so TLS sessions can be resumed
+    let connector = TlsConnector::from(client_tls_config(config)?);
+    let domain = ServerName::try_from(server_name)?;
+    let tls_stream = connector.connect(domain, stream).await?;
+    Ok(SocketFlowStream::Secure(TlsStream::from(tls_stream)))
+}
+
+fn build_client_tls_config(options: &ClientTlsOptions) -> Result<rustls::ClientConfig, Error> {
+    // Creating a cert store, to inject the TLS certificates
+    let mut root_cert_store = RootCertStore::empty();
+
+    // In the case you are using self-signed certificates on the server
+    // you are trying to connect, you must indicate a CA certificate of this server
+    // when connecting to it.
+    if let Some(file) = &options.ca_file {
+        for cert in load_certs(file)? {
+            root_cert_store.add(cert)?;
+        }
+    } else if options.native_roots {
+        // Some certificates of the system bundle may not be supported by rustls,
+        // and they are skipped, instead of failing the whole store
+        let (_added, _ignored) = root_cert_store.add_parsable_certificates(load_native_certs()?);
+    } else {
+        // Here we are adding TLS_SERVER_ROOTS to the certificate store,
+        // which is basically a reference to a list of trusted root certificates
+        // issue by a CA.
+        root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    }
+
+    let builder = client_config_builder()?;
+    let builder = if options.pinned_spki_sha256.is_empty() {
+        builder.with_root_certificates(root_cert_store)
+    } else {
+        let provider = builder.crypto_provider().clone();
+        let verifier =
+            PinnedServerVerifier::new(root_cert_store, &options.pinned_spki_sha256, provider)?;
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+    };
+
+    let mut tls_config = match (&options.client_cert_file, &options.client_key_file) {
+        (Some(cert_file), Some(key_file)) => {
+            builder.with_client_auth_cert(load_certs(cert_file)?, load_key(key_file)?)?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => {
+            return Err(Error::TlsConfigError(String::from(
+                "client_cert_file and client_key_file must be set together",
+            )))
+        }
+    };
+
+    tls_config.alpn_protocols = options
+        .alpn_protocols
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect();
+
+    Ok(tls_config)
+}
+
+/// Builds a rustls server config, without client authentication, from the PEM files
+/// of the certificate chain and its private key
+pub fn server_config_from_pem_files(
+    cert_file: &str,
+    key_file: &str,
+) -> Result<Arc<rustls::ServerConfig>, Error> {
+    server_config(load_certs(cert_file)?, load_key(key_file)?)
+}
+
+/// Builds a rustls server config, without client authentication, from the in-memory PEM
+/// of the certificate chain and its private key
+pub fn server_config_from_pem(
+    cert_pem: &[u8],
+    key_pem: &[u8],
+) -> Result<Arc<rustls::ServerConfig>, Error> {
+    server_config(parse_certs(cert_pem)?, parse_key(key_pem)?)
+}
+
+fn server_config(
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<Arc<rustls::ServerConfig>, Error> {
+    Ok(Arc::new(
+        server_config_builder()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?,
+    ))
+}
+
+/// Builds a rustls client config trusting only the CA certificates of the PEM file,
+/// which can be set as the `tls_config` of `ClientConfig`
+pub fn client_config_from_ca_file(ca_file: &str) -> Result<Arc<rustls::ClientConfig>, Error> {
+    client_config(load_certs(ca_file)?)
+}
+
+/// Builds a rustls client config trusting only the CA certificates of the in-memory PEM,
+/// which can be set as the `tls_config` of `ClientConfig`
+pub fn client_config_from_ca_pem(ca_pem: &[u8]) -> Result<Arc<rustls::ClientConfig>, Error> {
+    client_config(parse_certs(ca_pem)?)
+}
+
+fn client_config(
+    ca_certs: Vec<CertificateDer<'static>>,
+) -> Result<Arc<rustls::ClientConfig>, Error> {
+    let mut root_cert_store = RootCertStore::empty();
+    for cert in ca_certs {
+        root_cert_store.add(cert)?;
+    }
+    Ok(Arc::new(
+        client_config_builder()?
+            .with_root_certificates(root_cert_store)
+            .with_no_client_auth(),
+    ))
+}
+
+/// Loads all the certificates of a PEM file
+pub fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let mut reader = BufReader::new(File::open(Path::new(path))?);
+    read_certs(&mut reader, path)
+}
+
+/// Parses all the certificates of an in-memory PEM
+pub fn parse_certs(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, Error> {
+    read_certs(&mut BufReader::new(pem), IN_MEMORY_PEM)
+}
+
+/// Loads the first private key of a PEM file, which can be PKCS#8, PKCS#1 (RSA) or SEC1 (EC)
+pub fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, Error> {
+    let mut reader = BufReader::new(File::open(Path::new(path))?);
+    read_key(&mut reader, path)
+}
+
+/// Parses the first private key of an in-memory PEM, which can be PKCS#8, PKCS#1 (RSA)
+/// or SEC1 (EC)
+pub fn parse_key(pem: &[u8]) -> Result<PrivateKeyDer<'static>, Error> {
+    read_key(&mut BufReader::new(pem), IN_MEMORY_PEM)
+}
+
+fn read_certs(
+    reader: &mut dyn BufRead,
+    source: &str,
+) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let certs = rustls_pemfile::certs(reader)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| Error::InvalidPem(source.to_string(), err.to_string()))?;
+
+    if certs.is_empty() {
+        return Err(Error::NoCertificates(source.to_string()));
+    }
+    Ok(certs)
+}
+
+fn read_key(reader: &mut dyn BufRead, source: &str) -> Result<PrivateKeyDer<'static>, Error> {
+    rustls_pemfile::private_key(reader)
+        .map_err(|err| Error::InvalidPem(source.to_string(), err.to_string()))?
+        .ok_or_else(|| Error::NoPrivateKey(source.to_string()))
+}
+
+// Loads the trusted CA certificates of the operating system, which are the system bundle on
+// Unix-like systems, the keychain on macOS, and the certificate store on Windows.
+// SSL_CERT_FILE and SSL_CERT_DIR have precedence, when set
+fn load_native_certs() -> Result<Vec<CertificateDer<'static>>, Error> {
+    let result = rustls_native_certs::load_native_certs();
+    for err in &result.errors {
+        warn!("Failed to load some of the system CA certificates: {}", err);
+    }
+
+    if result.certs.is_empty() {
+        return Err(Error::NoNativeRoots);
+    }
+    Ok(result.certs)
+}
+
+// Verifies the server certificate chain as usual, and additionally requires the public key
+// of the server certificate to match one of the pinned keys.
+// Pins are the base64 encoded SHA-256 digest of the certificate SubjectPublicKeyInfo,
+// the same format used by HPKP and curl, optionally prefixed by "sha256/"
+#[derive(Debug)]
+struct PinnedServerVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<Vec<u8>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedServerVerifier {
+    fn new(
+        root_cert_store: RootCertStore,
+        pins: &[String],
+        provider: Arc<CryptoProvider>,
+    ) -> Result<Self, Error> {
+        let inner = WebPkiServerVerifier::builder_with_provider(
+            Arc::new(root_cert_store),
+            provider.clone(),
+        )
+        .build()
+        .map_err(|err| Error::TlsConfigError(err.to_string()))?;
+
+        let pins = pins
+            .iter()
+            .map(|pin| decode_spki_pin(pin))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Making sure a SHA-256 implementation is available, before any connection is made
+        sha256_provider(&provider)?;
+
+        Ok(Self {
+            inner,
+            pins,
+            provider,
+        })
+    }
+}
+
+impl ServerCertVerifier for PinnedServerVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        let spki = ParsedCertificate::try_from(end_entity)?.subject_public_key_info();
+        let sha256 = sha256_provider(&self.provider)
+            .map_err(|err| rustls::Error::General(err.to_string()))?;
+        let digest = sha256.hash(spki.as_ref());
+
+        if self
+            .pins
+            .iter()
+            .any(|pin| pin.as_slice() == digest.as_ref())
+        {
+            Ok(verified)
+        } else {
+            Err(rustls::Error::General(String::from(
+                "server certificate doesn't match any pinned public key",
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+pub(crate) fn decode_spki_pin(pin: &str) -> Result<Vec<u8>, Error> {
+    let encoded = pin.strip_prefix(SPKI_PIN_PREFIX).unwrap_or(pin);
+    match BASE64_STANDARD.decode(encoded) {
+        Ok(digest) if digest.len() == 32 => Ok(digest),
+        _ => Err(Error::TlsConfigError(format!(
+            "invalid SPKI pin: `{}`",
+            pin
+        ))),
+    }
+}
+
+// The crypto provider doesn't expose hash functions directly,
+// so the SHA-256 implementation is taken from one of its TLS 1.3 cipher suites
+fn sha256_provider(provider: &CryptoProvider) -> Result<&'static dyn Hash, Error> {
+    provider
+        .cipher_suites
+        .iter()
+        .filter_map(|suite| suite.tls13())
+        .map(|suite| suite.common.hash_provider)
+        .find(|hash| hash.algorithm() == HashAlgorithm::SHA256)
+        .ok_or_else(|| Error::TlsConfigError(String::from("SHA-256 isn't supported")))
+}
+
+/// A server certificate source that can be reloaded while the server is running.
+///
+/// It implements rustls `ResolvesServerCert`, so every new TLS handshake picks the current
+/// certificate, while connections that are already established stay up.
+/// Certificates can be rotated by calling `reload`, or automatically, with `watch`,
+/// which reloads them whenever the PEM files change.
+#[derive(Debug)]
+pub struct ReloadableCertResolver {
+    cert_file: String,
+    key_file: String,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadableCertResolver {
+    /// Loads the certificate chain and private key from PEM files
+    pub fn from_pem_files(cert_file: &str, key_file: &str) -> Result<Arc<Self>, Error> {
+        let provider = crypto_provider();
+        let certified_key = load_certified_key(cert_file, key_file, &provider)?;
+
+        Ok(Arc::new(Self {
+            cert_file: cert_file.to_string(),
+            key_file: key_file.to_string(),
+            provider,
+            current: RwLock::new(Arc::new(certified_key)),
+        }))
+    }
+
+    /// Reads the PEM files again, and uses the new certificate for the next handshakes.
+    /// If the files are invalid, the current certificate is kept
+    pub fn reload(&self) -> Result<(), Error> {
+        let certified_key = load_certified_key(&self.cert_file, &self.key_file, &self.provider)?;
+        *self
+            .current
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(certified_key);
+        Ok(())
+    }
+
+    /// Checks the PEM files modification time on every interval, reloading the certificate
+    /// when any of them change. The spawned task stops once the resolver is dropped
+    pub fn watch(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let resolver = Arc::downgrade(self);
+        let mut last_modified = self.modified();
+
+        tokio::spawn(async move {
+            let mut ticker = time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let Some(resolver) = resolver.upgrade() else {
+                    break;
+                };
+
+                let modified = resolver.modified();
+                if modified == last_modified {
+                    continue;
+                }
+                // Certificate and key are usually replaced one after the other, so a failed
+                // reload is retried on the next tick, until both files match
+                match resolver.reload() {
+                    Ok(()) => last_modified = modified,
+                    Err(err) => warn!("Failed to reload TLS certificate: {}", err),
+                }
+            }
+        })
+    }
+
+    /// Builds a rustls server config without client authentication, using this resolver.
+    /// For other setups, like mutual TLS, pass the resolver to `with_cert_resolver`
+    pub fn server_config(self: &Arc<Self>) -> Result<Arc<rustls::ServerConfig>, Error> {
+        Ok(Arc::new(
+            server_config_builder()?
+                .with_no_client_auth()
+                .with_cert_resolver(self.clone()),
+        ))
+    }
+
+    fn modified(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+        let modified = |path: &str| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+        (modified(&self.cert_file), modified(&self.key_file))
+    }
+}
+
+impl ResolvesServerCert for ReloadableCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(
+            self.current
+                .read()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .clone(),
+        )
+    }
+}
+
+fn load_certified_key(
+    cert_file: &str,
+    key_file: &str,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, Error> {
+    Ok(CertifiedKey::from_der(
+        load_certs(cert_file)?,
+        load_key(key_file)?,
+        provider,
+    )?)
+}
+
+/// A server certificate resolver that picks the certificate by the hostname the client
+/// requested through SNI, for serving several virtual hosts from the same server.
+///
+/// Hostnames are matched case-insensitively, and wildcard entries like `*.example.com`
+/// match a single subdomain level. Clients that don't send SNI, or request an unknown hostname,
+/// get the default certificate, if any, otherwise the handshake is aborted.
+/// Each hostname can use any certificate resolver, like `ReloadableCertResolver`,
+/// so virtual hosts can also be reloaded independently.
+#[derive(Debug, Default)]
+pub struct SniCertResolver {
+    resolvers: HashMap<String, Arc<dyn ResolvesServerCert>>,
+    default: Option<Arc<dyn ResolvesServerCert>>,
+}
+
+impl SniCertResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves the certificate from the PEM files for hostname
+    pub fn add_pem_files(
+        &mut self,
+        hostname: &str,
+        cert_file: &str,
+        key_file: &str,
+    ) -> Result<(), Error> {
+        let provider = crypto_provider();
+        let certified_key = Arc::new(load_certified_key(cert_file, key_file, &provider)?);
+        self.add(hostname, Arc::new(SingleCertResolver(certified_key)));
+        Ok(())
+    }
+
+    /// Serves the certificates picked by resolver for hostname
+    pub fn add(&mut self, hostname: &str, resolver: Arc<dyn ResolvesServerCert>) {
+        self.resolvers.insert(hostname.to_lowercase(), resolver);
+    }
+
+    /// Sets the resolver used when the requested hostname doesn't match any entry
+    pub fn set_default(&mut self, resolver: Arc<dyn ResolvesServerCert>) {
+        self.default = Some(resolver);
+    }
+
+    /// Builds a rustls server config without client authentication, using this resolver
+    pub fn server_config(self) -> Result<Arc<rustls::ServerConfig>, Error> {
+        Ok(Arc::new(
+            server_config_builder()?
+                .with_no_client_auth()
+                .with_cert_resolver(Arc::new(self)),
+        ))
+    }
+
+    fn find(&self, server_name: &str) -> Option<&Arc<dyn ResolvesServerCert>> {
+        let server_name = server_name.to_lowercase();
+        self.resolvers.get(&server_name).or_else(|| {
+            let (_, parent) = server_name.split_once('.')?;
+            self.resolvers.get(&format!("*.{}", parent))
+        })
+    }
+}
+
+impl ResolvesServerCert for SniCertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        client_hello
+            .server_name()
+            .and_then(|server_name| self.find(server_name))
+            .or(self.default.as_ref())?
+            .resolve(client_hello)
+    }
+}
+
+#[derive(Debug)]
+struct SingleCertResolver(Arc<CertifiedKey>);
+
+impl ResolvesServerCert for SingleCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+}
@@ -1,3 +1,4 @@
+use crate::error::Error;
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
 use rand::random;
@@ -16,3 +17,10 @@ pub(crate) fn generate_websocket_key() -> String {
     let random_bytes: [u8; 16] = random();
     BASE64_STANDARD.encode(random_bytes)
 }
+
+// Splits a host:port string, removing the brackets from IPv6 addresses
+pub(crate) fn split_host_port(target: &str) -> Result<(&str, u16), Error> {
+    let (host, port) = target.rsplit_once(':').ok_or(Error::URLNoPort)?;
+    let port = port.parse::<u16>().map_err(|_| Error::URLNoPort)?;
+    Ok((host.trim_start_matches('[').trim_end_matches(']'), port))
+}
@@ -1,9 +1,12 @@
 use crate::error::Error;
-use crate::frame::Frame;
+use crate::frame::{Frame, OpCode};
+use crate::message::PreparedMessage;
 use crate::stream::SocketFlowStream;
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
+use std::io;
 use tokio::io::{AsyncWriteExt, WriteHalf};
+use tokio::task::AbortHandle;
 
 pub enum WriterKind {
     Client,
@@ -11,20 +14,72 @@ pub enum WriterKind {
 }
 
 pub struct Writer {
-    write_half: WriteHalf<SocketFlowStream>,
+    // None once the connection was force closed
+    write_half: Option<WriteHalf<SocketFlowStream>>,
     kind: WriterKind,
+    close_sent: bool,
+    // The task reading from the other half of the socket
+    read_task: Option<AbortHandle>,
 }
 
 impl Writer {
     pub fn new(write_half: WriteHalf<SocketFlowStream>, kind: WriterKind) -> Self {
-        Self { write_half, kind }
+        Self {
+            write_half: Some(write_half),
+            kind,
+            close_sent: false,
+            read_task: None,
+        }
     }
 
     pub async fn write_frame(&mut self, frame: Frame, set_rsv1: bool) -> Result<(), Error> {
+        let is_close = frame.opcode == OpCode::Close;
         match self.kind {
-            WriterKind::Client => self.write_frame_client(frame, set_rsv1).await,
-            WriterKind::Server => self.write_frame_server(frame, set_rsv1).await,
+            WriterKind::Client => self.write_frame_client(frame, set_rsv1).await?,
+            WriterKind::Server => self.write_frame_server(frame, set_rsv1).await?,
+        }
+        if is_close {
+            self.close_sent = true;
+        }
+        Ok(())
+    }
+
+    // Writes all the frames of a message at once, so they aren't interleaved with the frames
+    // of other messages, written by other handles of the same connection.
+    // For compressed messages, regardless if it's fragmented or not, we always set the RSV1 bit
+    // for the first frame only.
+    pub async fn write_frames(&mut self, frames: Vec<Frame>) -> Result<(), Error> {
+        let mut set_rsv1_first_frame = !frames.is_empty() && frames[0].compressed;
+        for frame in frames {
+            self.write_frame(frame, set_rsv1_first_frame).await?;
+            set_rsv1_first_frame = false;
+        }
+        Ok(())
+    }
+
+    // Whether a Close frame was already sent, meaning the closing handshake was started
+    pub fn close_sent(&self) -> bool {
+        self.close_sent
+    }
+
+    pub(crate) fn set_read_task(&mut self, read_task: AbortHandle) {
+        self.read_task = Some(read_task);
+    }
+
+    // Drops the socket right away, without completing the closing handshake, stopping the task
+    // reading from it. Writing into the connection fails from then on
+    pub(crate) fn force_close(&mut self) {
+        if let Some(read_task) = self.read_task.take() {
+            read_task.abort();
         }
+        self.write_half = None;
+        self.close_sent = true;
+    }
+
+    fn write_half(&mut self) -> Result<&mut WriteHalf<SocketFlowStream>, Error> {
+        self.write_half
+            .as_mut()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected).into())
     }
 
     pub async fn write_frame_server(&mut self, frame: Frame, set_rsv1: bool) -> Result<(), Error> {
@@ -40,30 +95,43 @@ impl Writer {
             first_byte |= 0x40; // Set RSV1
         }
 
-        let payload_len = frame.payload.len();
+        self.write_half()?
+            .write_all(&server_frame_header(first_byte, frame.payload.len()))
+            .await?;
+        self.write_half()?.write_all(&frame.payload).await?;
 
-        self.write_half.write_all(&[first_byte]).await?;
+        Ok(())
+    }
 
-        // According to Websockets RFC, if the payload length is less or equal 125, it's written as a 8-bit unsigned integer
-        // if it's between 126 and 65535, it's represented by additional 8 bytes.
-        if payload_len <= 125 {
-            self.write_half.write_all(&[payload_len as u8]).await?;
-        } else if payload_len <= 65535 {
-            self.write_half
-                .write_all(&[126, (payload_len >> 8) as u8, payload_len as u8])
-                .await?;
-        } else {
-            let bytes = payload_len.to_be_bytes();
-            self.write_half
-                .write_all(&[
-                    127, bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6],
-                    bytes[7],
-                ])
-                .await?;
+    // Writes a message that was already encoded, for servers, or frames it again for clients,
+    // since their frames need to be masked, each one with its own mask.
+    // Messages larger than max_frame_size are fragmented, like any other message.
+    // Nothing is written once the closing handshake was started
+    pub async fn write_prepared(
+        &mut self,
+        prepared: &PreparedMessage,
+        compressed: bool,
+        max_frame_size: usize,
+    ) -> Result<(), Error> {
+        if self.close_sent {
+            return Err(Error::CloseSent);
         }
 
-        self.write_half.write_all(&frame.payload).await?;
-
+        match self.kind {
+            WriterKind::Server if prepared.fits(compressed, max_frame_size) => {
+                self.write_half()?
+                    .write_all(prepared.encoded(compressed))
+                    .await?
+            }
+            WriterKind::Server => {
+                self.write_frames(prepared.frames(compressed, max_frame_size))
+                    .await?
+            }
+            WriterKind::Client => {
+                self.write_frames(prepared.frames(false, max_frame_size))
+                    .await?
+            }
+        }
         Ok(())
     }
 
@@ -87,24 +155,24 @@ impl Writer {
         }
         let payload_len = frame.payload.len();
 
-        self.write_half.write_all(&[first_byte]).await?;
+        self.write_half()?.write_all(&[first_byte]).await?;
 
         if payload_len <= 125 {
             let length = 0b1000_0000 | payload_len as u8; // we set the MSB to 1 to signify that the payload is masked
-            self.write_half.write_all(&[length]).await?; // write the masked length
-            self.write_half.write_all(&mask).await?; // send the mask key
+            self.write_half()?.write_all(&[length]).await?; // write the masked length
+            self.write_half()?.write_all(&mask).await?; // send the mask key
         } else if payload_len <= 65535 {
-            self.write_half
+            self.write_half()?
                 .write_all(&[
                     126 | 0b1000_0000,
                     (payload_len >> 8) as u8,
                     payload_len as u8,
                 ])
                 .await?;
-            self.write_half.write_all(&mask).await?;
+            self.write_half()?.write_all(&mask).await?;
         } else {
             let bytes = payload_len.to_be_bytes();
-            self.write_half
+            self.write_half()?
                 .write_all(&[
                     127 | 0b1000_0000,
                     bytes[0],
@@ -117,7 +185,7 @@ impl Writer {
                     bytes[7],
                 ])
                 .await?;
-            self.write_half.write_all(&mask).await?;
+            self.write_half()?.write_all(&mask).await?;
         }
 
         let mut masked_payload: Vec<u8> = Vec::with_capacity(frame.payload.len());
@@ -125,8 +193,27 @@ impl Writer {
             masked_payload.push(byte ^ mask[i % 4]);
         }
 
-        self.write_half.write_all(&masked_payload).await?;
+        self.write_half()?.write_all(&masked_payload).await?;
 
         Ok(())
     }
 }
+
+// Encodes the header of an unmasked frame, as sent by servers
+pub(crate) fn server_frame_header(first_byte: u8, payload_len: usize) -> Vec<u8> {
+    let mut header = vec![first_byte];
+
+    // According to Websockets RFC, if the payload length is less or equal 125, it's written as a 8-bit unsigned integer
+    // if it's between 126 and 65535, it's represented by additional 2 bytes,
+    // and above that, by additional 8 bytes.
+    if payload_len <= 125 {
+        header.push(payload_len as u8);
+    } else if payload_len <= 65535 {
+        header.extend_from_slice(&[126, (payload_len >> 8) as u8, payload_len as u8]);
+    } else {
+        header.push(127);
+        header.extend_from_slice(&(payload_len as u64).to_be_bytes());
+    }
+
+    header
+}